@@ -1,14 +1,18 @@
 use librqbit::dht::Id20;
 use librqbit::{
     AddTorrent, AddTorrentOptions, AddTorrentResponse, ManagedTorrent, Session, SessionOptions,
+    SessionPersistenceConfig, TorrentStats,
 };
 use snafu::Snafu;
 use std::fmt::Debug;
 use std::ops::Range;
+use std::path::PathBuf;
 use std::sync::{Arc, OnceLock};
 
 use crate::util::config::Download;
 
+pub mod scrape;
+
 #[derive(Clone)]
 pub struct SessionGuard(Arc<Session>);
 
@@ -26,8 +30,12 @@ impl SessionGuard {
             return Ok(session.clone());
         }
 
+        // 保存会话状态，重启后不需要重新获取元数据和校验已下载的分块
         let option = SessionOptions {
-            persistence: None,
+            persistence: Some(SessionPersistenceConfig::Json {
+                folder: Some(download.state_dir()),
+            }),
+            fastresume: true,
             enable_upnp_port_forwarding: download.upnp,
             listen_port_range: Some(Range {
                 start: download.download_port,
@@ -47,7 +55,47 @@ impl SessionGuard {
         Ok(session)
     }
 
-    pub async fn add_torrent(&self, magnet: &str) -> Result<(usize, Arc<ManagedTorrent>), Error> {
+    /// 只获取元数据，返回种子中的文件列表；种子已经在会话中时返回 None
+    pub async fn list_files(&self, magnet: &str) -> Result<Option<Vec<PathBuf>>, Error> {
+        let session = self.0.clone();
+
+        let response = session
+            .add_torrent(
+                AddTorrent::from_url(magnet),
+                Some(AddTorrentOptions {
+                    list_only: true,
+                    ..Default::default()
+                }),
+            )
+            .await
+            .map_err(|error| Error::AddTorrent {
+                error: error.to_string(),
+            })?;
+
+        let AddTorrentResponse::ListOnly(list) = response else {
+            return Ok(None);
+        };
+        let files = list
+            .info
+            .iter_file_details()
+            .and_then(|files| {
+                files
+                    .map(|f| f.filename.to_string().map(PathBuf::from))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(|error| Error::AddTorrent {
+                error: error.to_string(),
+            })?;
+
+        Ok(Some(files))
+    }
+
+    /// `only_files` 为 None 时下载全部文件
+    pub async fn add_torrent(
+        &self,
+        magnet: &str,
+        only_files: Option<Vec<usize>>,
+    ) -> Result<(usize, Arc<ManagedTorrent>), Error> {
         let session = self.0.clone();
 
         let response = session
@@ -55,6 +103,7 @@ impl SessionGuard {
                 AddTorrent::from_url(magnet),
                 Some(AddTorrentOptions {
                     overwrite: true,
+                    only_files,
                     ..Default::default()
                 }),
             )
@@ -74,6 +123,19 @@ impl SessionGuard {
         Ok((id, handle))
     }
 
+    /// 种子的统计信息，种子不在会话中时返回 None
+    pub fn stats(&self, info_hash: Id20) -> Option<TorrentStats> {
+        self.0
+            .get(librqbit::api::TorrentIdOrHash::Hash(info_hash))
+            .map(|handle| handle.stats())
+    }
+
+    /// 会话中的所有种子，包括从保存的状态中恢复的
+    pub fn torrents(&self) -> Vec<(usize, Arc<ManagedTorrent>)> {
+        self.0
+            .with_torrents(|torrents| torrents.map(|(id, handle)| (id, handle.clone())).collect())
+    }
+
     pub async fn delete_torrent_by_hash(&self, info_hash: Id20) -> Result<(), Error> {
         let session = self.0.clone();
         session
@@ -100,12 +162,9 @@ impl SessionGuard {
 
     pub async fn pause_torrent_by_handle(&self, handle: &Arc<ManagedTorrent>) -> Result<(), Error> {
         let session = self.0.clone();
-        session
-            .pause(handle)
-            .await
-            .map_err(|error| Error::Delete {
-                error: error.to_string(),
-            })?;
+        session.pause(handle).await.map_err(|error| Error::Delete {
+            error: error.to_string(),
+        })?;
 
         Ok(())
     }
@@ -136,7 +195,7 @@ mod test {
         let settings = util::config::Settings::load_from_file("settings.json").unwrap();
         let session = SessionGuard::get(settings.download).await.unwrap();
         let info_hash = session
-            .add_torrent("magnet:?xt=urn:btih:5d9140ed25be2cff3b981566792b668ab6976f58&tr=http%3a%2f%2ft.nyaatracker.com%2fannounce&tr=http%3a%2f%2ftracker.kamigami.org%3a2710%2fannounce&tr=http%3a%2f%2fshare.camoe.cn%3a8080%2fannounce&tr=http%3a%2f%2fopentracker.acgnx.se%2fannounce&tr=http%3a%2f%2fanidex.moe%3a6969%2fannounce&tr=http%3a%2f%2ft.acg.rip%3a6699%2fannounce&tr=https%3a%2f%2ftr.bangumi.moe%3a9696%2fannounce&tr=udp%3a%2f%2ftr.bangumi.moe%3a6969%2fannounce&tr=http%3a%2f%2fopen.acgtracker.com%3a1096%2fannounce&tr=udp%3a%2f%2ftracker.opentrackr.org%3a1337%2fannounce")
+            .add_torrent("magnet:?xt=urn:btih:5d9140ed25be2cff3b981566792b668ab6976f58&tr=http%3a%2f%2ft.nyaatracker.com%2fannounce&tr=http%3a%2f%2ftracker.kamigami.org%3a2710%2fannounce&tr=http%3a%2f%2fshare.camoe.cn%3a8080%2fannounce&tr=http%3a%2f%2fopentracker.acgnx.se%2fannounce&tr=http%3a%2f%2fanidex.moe%3a6969%2fannounce&tr=http%3a%2f%2ft.acg.rip%3a6699%2fannounce&tr=https%3a%2f%2ftr.bangumi.moe%3a9696%2fannounce&tr=udp%3a%2f%2ftr.bangumi.moe%3a6969%2fannounce&tr=http%3a%2f%2fopen.acgtracker.com%3a1096%2fannounce&tr=udp%3a%2f%2ftracker.opentrackr.org%3a1337%2fannounce", None)
             .await
             .unwrap()
             .1
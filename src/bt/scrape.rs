@@ -0,0 +1,281 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use percent_encoding::{percent_encode, NON_ALPHANUMERIC};
+use tokio::{net::UdpSocket, task::JoinSet};
+use url::Url;
+
+use crate::util::reqwest::client;
+
+// 同一个种子在这段时间内不重复查询，避免过于频繁地请求 tracker
+const CACHE_TIME: Duration = Duration::from_secs(30 * 60);
+const TIMEOUT: Duration = Duration::from_secs(15);
+// BEP 15 中 connect 请求固定的协议标识
+const UDP_PROTOCOL_ID: u64 = 0x41727101980;
+
+// info_hash -> (查询时间, 做种者数量)
+type Cache = HashMap<String, (Instant, Option<u32>)>;
+
+static CACHE: Lazy<Mutex<Cache>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 向磁力链接中的 tracker 查询做种者数量，取各 tracker 中的最大值，
+/// 都查询失败或没有 tracker 时返回 None
+pub async fn seeders(magnet: &str, info_hash: &str) -> Option<u32> {
+    if let Some((at, seeders)) = CACHE.lock().unwrap().get(info_hash) {
+        if at.elapsed() < CACHE_TIME {
+            return *seeders;
+        }
+    }
+
+    let hash = decode_hex(info_hash)?;
+    let mut set = JoinSet::new();
+    for tracker in trackers(magnet) {
+        set.spawn(async move {
+            let ret = match tracker.scheme() {
+                "http" | "https" => {
+                    tokio::time::timeout(TIMEOUT, scrape_http(&tracker, hash)).await
+                }
+                "udp" => tokio::time::timeout(TIMEOUT, scrape_udp(&tracker, hash)).await,
+                _ => return None,
+            };
+            let ret = ret.ok().flatten();
+            if ret.is_none() {
+                tracing::debug!("Error scraping {}", tracker);
+            }
+            ret
+        });
+    }
+    let mut seeders = None;
+    while let Some(ret) = set.join_next().await {
+        if let Ok(Some(n)) = ret {
+            seeders = seeders.max(Some(n));
+        }
+    }
+
+    CACHE
+        .lock()
+        .unwrap()
+        .insert(info_hash.to_owned(), (Instant::now(), seeders));
+    seeders
+}
+
+fn trackers(magnet: &str) -> Vec<Url> {
+    let Ok(url) = Url::parse(magnet) else {
+        return Vec::new();
+    };
+    url.query_pairs()
+        .filter(|(key, _)| key == "tr")
+        .filter_map(|(_, value)| Url::parse(&value).ok())
+        .collect()
+}
+
+fn decode_hex(hex: &str) -> Option<[u8; 20]> {
+    if hex.len() != 40 {
+        return None;
+    }
+    let mut bytes = [0u8; 20];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+// BEP 48：把 announce 地址最后一段中的 announce 换成 scrape，不以 announce 开头的不支持
+fn scrape_url(tracker: &Url, hash: &[u8; 20]) -> Option<String> {
+    let mut url = tracker.clone();
+    let path = url.path().to_owned();
+    let (dir, name) = path.rsplit_once('/')?;
+    let rest = name.strip_prefix("announce")?;
+    url.set_path(&format!("{}/scrape{}", dir, rest));
+
+    // info_hash 是原始字节，不能交给 Url 按 UTF-8 编码
+    let info_hash = percent_encode(hash, NON_ALPHANUMERIC);
+    Some(match url.query() {
+        Some(_) => format!("{}&info_hash={}", url, info_hash),
+        None => format!("{}?info_hash={}", url, info_hash),
+    })
+}
+
+async fn scrape_http(tracker: &Url, hash: [u8; 20]) -> Option<u32> {
+    let url = scrape_url(tracker, &hash)?;
+    let res = client().get(url).send().await.ok()?;
+    if !res.status().is_success() {
+        return None;
+    }
+    let body = res.bytes().await.ok()?;
+    parse_scrape(&body, &hash)
+}
+
+// 响应形如 d5:filesd20:<info_hash>d8:completei5e10:downloadedi10e10:incompletei2eeee
+fn parse_scrape(body: &[u8], hash: &[u8; 20]) -> Option<u32> {
+    let (value, _) = Bencode::parse(body)?;
+    let complete = value.get(b"files")?.get(hash)?.get(b"complete")?;
+    match complete {
+        Bencode::Int(n) => u32::try_from(*n).ok(),
+        _ => None,
+    }
+}
+
+// BEP 15：先 connect 取得 connection_id，再发送 scrape 请求
+async fn scrape_udp(tracker: &Url, hash: [u8; 20]) -> Option<u32> {
+    let host = tracker.host_str()?;
+    let port = tracker.port()?;
+    let addr = tokio::net::lookup_host((host, port)).await.ok()?.next()?;
+    let bind = if addr.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(bind).await.ok()?;
+    socket.connect(addr).await.ok()?;
+
+    let transaction = rand::random::<u32>();
+    let mut request = Vec::with_capacity(16);
+    request.extend_from_slice(&UDP_PROTOCOL_ID.to_be_bytes());
+    request.extend_from_slice(&0u32.to_be_bytes());
+    request.extend_from_slice(&transaction.to_be_bytes());
+    let response = exchange(&socket, &request, 16).await?;
+    if !is_reply(&response, 0, transaction) {
+        return None;
+    }
+    let connection_id = &response[8..16];
+
+    let transaction = rand::random::<u32>();
+    let mut request = Vec::with_capacity(36);
+    request.extend_from_slice(connection_id);
+    request.extend_from_slice(&2u32.to_be_bytes());
+    request.extend_from_slice(&transaction.to_be_bytes());
+    request.extend_from_slice(&hash);
+    let response = exchange(&socket, &request, 20).await?;
+    if !is_reply(&response, 2, transaction) {
+        return None;
+    }
+    Some(u32::from_be_bytes(response[8..12].try_into().unwrap()))
+}
+
+// 发送请求并读取至少 len 字节的响应
+async fn exchange(socket: &UdpSocket, request: &[u8], len: usize) -> Option<Vec<u8>> {
+    socket.send(request).await.ok()?;
+    let mut buf = vec![0u8; 1024];
+    let n = socket.recv(&mut buf).await.ok()?;
+    buf.truncate(n);
+    (n >= len).then_some(buf)
+}
+
+// 响应的前 8 个字节是 action 和请求中的 transaction_id
+fn is_reply(response: &[u8], action: u32, transaction: u32) -> bool {
+    response[..4] == action.to_be_bytes() && response[4..8] == transaction.to_be_bytes()
+}
+
+/// 只用于解析 scrape 响应的 bencode
+#[derive(Debug, PartialEq)]
+enum Bencode<'a> {
+    Int(i64),
+    Bytes(&'a [u8]),
+    List(Vec<Bencode<'a>>),
+    Dict(Vec<(&'a [u8], Bencode<'a>)>),
+}
+
+impl<'a> Bencode<'a> {
+    // 返回解析出的值和剩余的字节
+    fn parse(data: &'a [u8]) -> Option<(Self, &'a [u8])> {
+        match data.first()? {
+            b'i' => {
+                let end = data.iter().position(|&b| b == b'e')?;
+                let n = std::str::from_utf8(&data[1..end]).ok()?.parse().ok()?;
+                Some((Bencode::Int(n), &data[end + 1..]))
+            }
+            b'l' => {
+                let mut rest = &data[1..];
+                let mut list = Vec::new();
+                while *rest.first()? != b'e' {
+                    let (value, next) = Self::parse(rest)?;
+                    list.push(value);
+                    rest = next;
+                }
+                Some((Bencode::List(list), &rest[1..]))
+            }
+            b'd' => {
+                let mut rest = &data[1..];
+                let mut dict = Vec::new();
+                while *rest.first()? != b'e' {
+                    let (Bencode::Bytes(key), next) = Self::parse(rest)? else {
+                        return None;
+                    };
+                    let (value, next) = Self::parse(next)?;
+                    dict.push((key, value));
+                    rest = next;
+                }
+                Some((Bencode::Dict(dict), &rest[1..]))
+            }
+            b'0'..=b'9' => {
+                let colon = data.iter().position(|&b| b == b':')?;
+                let len: usize = std::str::from_utf8(&data[..colon]).ok()?.parse().ok()?;
+                let bytes = data.get(colon + 1..colon + 1 + len)?;
+                Some((Bencode::Bytes(bytes), &data[colon + 1 + len..]))
+            }
+            _ => None,
+        }
+    }
+
+    fn get(&self, key: &[u8]) -> Option<&Self> {
+        match self {
+            Bencode::Dict(dict) => dict.iter().find(|(k, _)| *k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "5d9140ed25be2cff3b981566792b668ab6976f58";
+
+    #[test]
+    fn test_scrape_url() {
+        let hash = decode_hex(HASH).unwrap();
+        let url = scrape_url(
+            &Url::parse("http://t.nyaatracker.com/announce").unwrap(),
+            &hash,
+        );
+        assert_eq!(
+            url.as_deref(),
+            Some("http://t.nyaatracker.com/scrape?info_hash=%5D%91%40%ED%25%BE%2C%FF%3B%98%15fy%2Bf%8A%B6%97oX")
+        );
+        let url = scrape_url(
+            &Url::parse("https://tr.bangumi.moe:9696/announce.php?passkey=a").unwrap(),
+            &hash,
+        )
+        .unwrap();
+        assert!(url.starts_with("https://tr.bangumi.moe:9696/scrape.php?passkey=a&info_hash="));
+        assert!(scrape_url(&Url::parse("http://example.com/a").unwrap(), &hash).is_none());
+    }
+
+    #[test]
+    fn test_parse_scrape() {
+        let hash = decode_hex(HASH).unwrap();
+        let mut body = b"d5:filesd20:".to_vec();
+        body.extend_from_slice(&hash);
+        body.extend_from_slice(b"d8:completei5e10:downloadedi10e10:incompletei2eeee");
+        assert_eq!(parse_scrape(&body, &hash), Some(5));
+        assert_eq!(parse_scrape(b"d5:filesdee", &hash), None);
+        assert_eq!(parse_scrape(b"d14:failure reason", &hash), None);
+    }
+
+    #[test]
+    fn test_trackers() {
+        let magnet = format!(
+            "magnet:?xt=urn:btih:{}&tr=http%3a%2f%2ft.nyaatracker.com%2fannounce&tr=udp%3a%2f%2ftracker.opentrackr.org%3a1337%2fannounce",
+            HASH
+        );
+        let trackers = trackers(&magnet);
+        assert_eq!(trackers.len(), 2);
+        assert_eq!(trackers[1].scheme(), "udp");
+        assert_eq!(trackers[1].port(), Some(1337));
+    }
+}
@@ -0,0 +1,151 @@
+use regex::Regex;
+use snafu::{ResultExt, Snafu};
+
+use crate::util::{config::Rule, title};
+
+/// 编译后的过滤规则
+pub struct Filter {
+    rules: Vec<CompiledRule>,
+}
+
+struct CompiledRule {
+    rule: Rule,
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl Filter {
+    pub fn new(rules: &[Rule]) -> Result<Self, Error> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                Ok(CompiledRule {
+                    include: compile(rule, &rule.include)?,
+                    exclude: compile(rule, &rule.exclude)?,
+                    rule: rule.clone(),
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(Self { rules })
+    }
+
+    /// 检查标题是否被规则拒绝，返回拒绝的原因
+    pub fn check(&self, title: &str, bangumi_tv_id: u64) -> Option<String> {
+        self.rules
+            .iter()
+            .filter(|r| r.rule.bangumi_tv_id.is_none_or(|id| id == bangumi_tv_id))
+            .find_map(|r| r.check(title).map(|e| format!("{}: {}", r.rule.name, e)))
+    }
+}
+
+impl CompiledRule {
+    fn check(&self, title: &str) -> Option<String> {
+        if !self.include.is_empty() && !self.include.iter().any(|r| r.is_match(title)) {
+            return Some("not included".to_owned());
+        }
+
+        if let Some(r) = self.exclude.iter().find(|r| r.is_match(title)) {
+            return Some(format!("excluded by {}", r.as_str()));
+        }
+
+        let subgroup = title::subgroup(title).unwrap_or_default();
+        if !self.rule.subgroups.is_empty()
+            && !self
+                .rule
+                .subgroups
+                .iter()
+                .any(|s| s.eq_ignore_ascii_case(subgroup))
+        {
+            return Some(format!("subgroup {} not allowed", subgroup));
+        }
+        if self
+            .rule
+            .exclude_subgroups
+            .iter()
+            .any(|s| s.eq_ignore_ascii_case(subgroup))
+        {
+            return Some(format!("subgroup {} excluded", subgroup));
+        }
+
+        if !self.rule.resolutions.is_empty() {
+            match title::resolution(title) {
+                Some(r) if self.rule.resolutions.contains(&r) => {}
+                Some(r) => return Some(format!("resolution {}p not allowed", r)),
+                None => return Some("unknown resolution".to_owned()),
+            }
+        }
+
+        if !self.rule.languages.is_empty()
+            && !self
+                .rule
+                .languages
+                .iter()
+                .any(|l| title::contains_keyword(title, l))
+        {
+            return Some("language not matched".to_owned());
+        }
+
+        None
+    }
+}
+
+fn compile(rule: &Rule, patterns: &[String]) -> Result<Vec<Regex>, Error> {
+    patterns
+        .iter()
+        .map(|p| {
+            Regex::new(p).context(RegexSnafu {
+                rule: rule.name.clone(),
+                pattern: p.clone(),
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid regex {pattern} in rule {rule}: {source}"))]
+    Regex {
+        source: regex::Error,
+        rule: String,
+        pattern: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        let filter = Filter::new(&[
+            Rule {
+                name: "global".into(),
+                exclude: vec![r"(?i)\b720p\b".into(), "合集".into()],
+                languages: vec!["CHS".into(), "简".into()],
+                ..Default::default()
+            },
+            Rule {
+                name: "dungeon".into(),
+                bangumi_tv_id: Some(395378),
+                subgroups: vec!["Sakurato".into()],
+                resolutions: vec![1080],
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+
+        let title = "[Sakurato] Dungeon Meshi [17][AVC-8bit 1080p AAC][CHS].mp4";
+        assert_eq!(filter.check(title, 395378), None);
+
+        let title = "[Sakurato] Dungeon Meshi [17][AVC-8bit 1080p AAC][CHT].mp4";
+        assert!(filter.check(title, 395378).unwrap().starts_with("global"));
+
+        let title = "[LoliHouse] Dungeon Meshi - 17 [WebRip 720p HEVC-10bit AAC][简繁内封字幕]";
+        assert!(filter.check(title, 395378).unwrap().starts_with("global"));
+
+        let title = "[LoliHouse] Dungeon Meshi - 17 [WebRip 1080p HEVC-10bit AAC][简繁内封字幕]";
+        assert!(filter.check(title, 395378).unwrap().starts_with("dungeon"));
+        assert_eq!(filter.check(title, 1), None);
+    }
+}
@@ -0,0 +1,336 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{Datelike, NaiveDate};
+use snafu::Snafu;
+
+use crate::util::config::{self, LayoutPreset};
+
+// 原有的布局，如 2024年4月/星期三/Title/02 - name.mkv
+const QUARTER: &str =
+    "{year}年{quarter_month}月/{weekday}/{title}/{episode:02} - {episode_name}.{ext}";
+// Jellyfin、Plex、Emby 可以识别的布局
+const MEDIA_SERVER: &str =
+    "{title} ({year})/Season {season:02}/{title} - S{season:02}E{episode:02} - {episode_name}.{ext}";
+
+/// 编译后的上传路径模板
+#[derive(Debug)]
+pub struct Layout {
+    segments: Vec<Vec<Token>>,
+    /// 是否生成 nfo 和海报
+    pub metadata: bool,
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Literal(String),
+    Var { name: Var, width: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Var {
+    Title,
+    Season,
+    Episode,
+    EpisodeName,
+    Year,
+    Quarter,
+    QuarterMonth,
+    Weekday,
+    Subgroup,
+    Resolution,
+    Ext,
+}
+
+/// 渲染路径时可用的变量，为 None 表示无法获取
+#[derive(Debug, Default)]
+pub struct Vars {
+    pub title: String,
+    pub season: Option<u32>,
+    pub episode: Option<u32>,
+    pub episode_name: Option<String>,
+    pub air_date: Option<NaiveDate>,
+    pub weekday: String,
+    pub subgroup: Option<String>,
+    pub resolution: Option<u32>,
+    pub ext: String,
+}
+
+impl Layout {
+    pub fn new(layout: &config::Layout) -> Result<Self, Error> {
+        let template = match (&layout.template, &layout.preset) {
+            (Some(template), _) => template.as_str(),
+            (None, LayoutPreset::Quarter) => QUARTER,
+            (None, LayoutPreset::MediaServer) => MEDIA_SERVER,
+        };
+
+        let segments = template
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(parse_segment)
+            .collect::<Result<Vec<_>, _>>()?;
+        if segments.is_empty() {
+            return EmptySnafu.fail();
+        }
+
+        Ok(Self {
+            segments,
+            metadata: layout.metadata,
+        })
+    }
+
+    /// 生成上传路径，缺少文件名需要的变量时保留原始文件名
+    pub fn path(&self, vars: &Vars, file_name: &str) -> PathBuf {
+        let (file, dirs) = self.segments.split_last().unwrap();
+        let dir = dirs
+            .iter()
+            .map(|s| render(s, vars))
+            .collect::<Option<PathBuf>>()
+            // 目录也无法生成时放在动画标题目录下
+            .unwrap_or_else(|| PathBuf::from(sanitize_filename(&vars.title)));
+
+        match render(file, vars) {
+            Some(file) => dir.join(file),
+            None => dir.join(file_name),
+        }
+    }
+
+    /// 剧集所在的目录，即最后一个包含 `{title}` 的目录，用于存放 tvshow.nfo 和海报
+    pub fn show_dir(&self, path: &Path) -> PathBuf {
+        let parent = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        let (_, dirs) = self.segments.split_last().unwrap();
+        let depth = dirs.iter().rposition(|s| {
+            s.iter().any(|t| {
+                matches!(
+                    t,
+                    Token::Var {
+                        name: Var::Title,
+                        ..
+                    }
+                )
+            })
+        });
+
+        match depth {
+            // 路径是按模板生成的，而不是回退的结果
+            Some(depth) if path.components().count() == self.segments.len() => {
+                path.components().take(depth + 1).collect()
+            }
+            _ => parent,
+        }
+    }
+}
+
+fn parse_segment(segment: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut rest = segment;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            tokens.push(Token::Literal(rest[..start].to_owned()));
+        }
+        let end = rest[start..]
+            .find('}')
+            .map(|e| start + e)
+            .ok_or_else(|| UnclosedSnafu { segment }.build())?;
+
+        let placeholder = &rest[start + 1..end];
+        let (name, width) = match placeholder.split_once(':') {
+            Some((name, width)) => {
+                let width = width
+                    .parse()
+                    .map_err(|_| InvalidWidthSnafu { placeholder }.build())?;
+                (name, width)
+            }
+            None => (placeholder, 0),
+        };
+        let name = match name {
+            "title" => Var::Title,
+            "season" => Var::Season,
+            "episode" => Var::Episode,
+            "episode_name" => Var::EpisodeName,
+            "year" => Var::Year,
+            "quarter" => Var::Quarter,
+            "quarter_month" => Var::QuarterMonth,
+            "weekday" => Var::Weekday,
+            "subgroup" => Var::Subgroup,
+            "resolution" => Var::Resolution,
+            "ext" => Var::Ext,
+            _ => return UnknownVarSnafu { name }.fail(),
+        };
+        tokens.push(Token::Var { name, width });
+
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        tokens.push(Token::Literal(rest.to_owned()));
+    }
+
+    Ok(tokens)
+}
+
+fn render(tokens: &[Token], vars: &Vars) -> Option<String> {
+    let mut ret = String::new();
+    for token in tokens {
+        match token {
+            Token::Literal(s) => ret.push_str(s),
+            Token::Var { name, width } => {
+                let value = match name {
+                    Var::Title => sanitize_filename(&vars.title),
+                    Var::Season => format!("{:0width$}", vars.season?),
+                    Var::Episode => format!("{:0width$}", vars.episode?),
+                    Var::EpisodeName => sanitize_filename(vars.episode_name.as_deref()?),
+                    Var::Year => format!("{:0width$}", vars.air_date?.year()),
+                    Var::Quarter => format!("{:0width$}", quarter(vars.air_date?)),
+                    Var::QuarterMonth => format!("{:0width$}", quarter(vars.air_date?) * 3 - 2),
+                    Var::Weekday => sanitize_filename(&vars.weekday),
+                    Var::Subgroup => sanitize_filename(vars.subgroup.as_deref()?),
+                    Var::Resolution => format!("{}p", vars.resolution?),
+                    Var::Ext => vars.ext.clone(),
+                };
+                if value.is_empty() {
+                    return None;
+                }
+                ret.push_str(&value);
+            }
+        }
+    }
+
+    Some(ret)
+}
+
+// 开播日期最接近的季度，年底开播的算作当年十月
+fn quarter(date: NaiveDate) -> u32 {
+    let year = date.year();
+    (1..=4)
+        .min_by_key(|q| {
+            let start = NaiveDate::from_ymd_opt(year, q * 3 - 2, 1).unwrap();
+            (date - start).num_days().abs()
+        })
+        .unwrap()
+}
+
+pub fn sanitize_filename(filename: &str) -> String {
+    // Define a set of special characters to be replaced
+    let special_chars = ['\\', '/', ':', '*', '?', '"', '<', '>', '|'];
+
+    // Replace each special character with an underscore
+    filename
+        .chars()
+        .map(|c| if special_chars.contains(&c) { '_' } else { c })
+        .collect()
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Path template is empty"))]
+    Empty,
+
+    #[snafu(display("Unclosed placeholder in {}", segment))]
+    Unclosed { segment: String },
+
+    #[snafu(display("Invalid width in placeholder {}", placeholder))]
+    InvalidWidth { placeholder: String },
+
+    #[snafu(display("Unknown placeholder {}", name))]
+    UnknownVar { name: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> Vars {
+        Vars {
+            title: "葬送的芙莉莲".into(),
+            season: Some(1),
+            episode: Some(2),
+            episode_name: Some("不是魔法也可以".into()),
+            air_date: NaiveDate::from_ymd_opt(2023, 9, 29),
+            weekday: "星期五".into(),
+            subgroup: Some("LoliHouse".into()),
+            resolution: Some(1080),
+            ext: "mkv".into(),
+        }
+    }
+
+    fn layout(preset: LayoutPreset, template: Option<&str>) -> Result<Layout, Error> {
+        Layout::new(&config::Layout {
+            preset,
+            template: template.map(Into::into),
+            metadata: false,
+        })
+    }
+
+    #[test]
+    fn test_presets() {
+        let quarter = layout(LayoutPreset::Quarter, None).unwrap();
+        assert_eq!(
+            quarter.path(&vars(), "raw.mkv"),
+            PathBuf::from("2023年10月/星期五/葬送的芙莉莲/02 - 不是魔法也可以.mkv")
+        );
+
+        let media_server = layout(LayoutPreset::MediaServer, None).unwrap();
+        assert_eq!(
+            media_server.path(&vars(), "raw.mkv"),
+            PathBuf::from(
+                "葬送的芙莉莲 (2023)/Season 01/葬送的芙莉莲 - S01E02 - 不是魔法也可以.mkv"
+            )
+        );
+    }
+
+    #[test]
+    fn test_missing_vars() {
+        let media_server = layout(LayoutPreset::MediaServer, None).unwrap();
+        let vars = Vars {
+            episode_name: None,
+            ..vars()
+        };
+        assert_eq!(
+            media_server.path(&vars, "raw.mkv"),
+            PathBuf::from("葬送的芙莉莲 (2023)/Season 01/raw.mkv")
+        );
+
+        let vars = Vars {
+            season: None,
+            episode: None,
+            ..vars
+        };
+        assert_eq!(
+            media_server.path(&vars, "raw.mkv"),
+            PathBuf::from("葬送的芙莉莲/raw.mkv")
+        );
+    }
+
+    #[test]
+    fn test_show_dir() {
+        let quarter = layout(LayoutPreset::Quarter, None).unwrap();
+        let path = quarter.path(&vars(), "raw.mkv");
+        assert_eq!(
+            quarter.show_dir(&path),
+            PathBuf::from("2023年10月/星期五/葬送的芙莉莲")
+        );
+
+        let media_server = layout(LayoutPreset::MediaServer, None).unwrap();
+        let path = media_server.path(&vars(), "raw.mkv");
+        assert_eq!(
+            media_server.show_dir(&path),
+            PathBuf::from("葬送的芙莉莲 (2023)")
+        );
+    }
+
+    #[test]
+    fn test_custom_template() {
+        let custom = layout(
+            LayoutPreset::Quarter,
+            Some("{year}-Q{quarter}/{title}/[{subgroup}] {title} - {episode:03} [{resolution}].{ext}"),
+        )
+        .unwrap();
+        assert_eq!(
+            custom.path(&vars(), "raw.mkv"),
+            PathBuf::from("2023-Q4/葬送的芙莉莲/[LoliHouse] 葬送的芙莉莲 - 002 [1080p].mkv")
+        );
+
+        assert!(layout(LayoutPreset::Quarter, Some("{title}/{unknown}")).is_err());
+        assert!(layout(LayoutPreset::Quarter, Some("{title/{episode}")).is_err());
+        assert!(layout(LayoutPreset::Quarter, Some("{episode:x}")).is_err());
+    }
+}
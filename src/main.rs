@@ -1,12 +1,17 @@
 mod bt;
+mod filter;
+mod layout;
+mod media;
+mod metadata;
+mod release;
 mod store;
 mod subscribe;
+mod target;
 mod util;
 mod worker;
 
-use subscribe::get_feed;
+use std::sync::Arc;
 use tokio::signal;
-use tracing::{debug, error};
 use tracing::{info, Level};
 use tracing_subscriber::filter::FilterFn;
 use tracing_subscriber::fmt;
@@ -30,59 +35,50 @@ async fn main() {
         }));
     tracing_subscriber::registry().with(filtered_layer).init();
 
+    // 一次性命令，执行后退出
+    let args = std::env::args().collect::<Vec<_>>();
+    if let Some(command) = args.get(1) {
+        run_command(command, &args[2..]).await;
+        return;
+    }
+
     let settings = util::config::Settings::load_from_file("settings.json").unwrap();
     let _ = init_client(settings.proxy).unwrap();
     if let Some(llama) = settings.llama {
-        llama::Llama::init(&llama.model, &llama.url, &llama.token).unwrap();
+        llama::Llama::init(&llama).unwrap();
     }
 
-    let _upload_worker = worker::upload_video(settings.storage).await;
-    let download_worker = DownloadHandle::init(settings.download).await.unwrap();
+    let layout = layout::Layout::new(&settings.layout).unwrap();
+    let _upload_worker = worker::upload_video(
+        settings.storage,
+        settings.subscribe.clone(),
+        layout,
+        settings.files.clone(),
+        settings.upload,
+    )
+    .await;
 
-    info!("Service started");
-    let download_worker_cloned = download_worker.clone();
-    tokio::spawn(async move {
-        let subscribe = settings.subscribe;
-        let db = store::Db::get_subscribe().unwrap();
-
-        loop {
-            info!("Checking feed");
-            let feed = get_feed(&subscribe).await;
-
-            if let Err(e) = feed {
-                tracing::error!("Error getting feed: {}", e);
-                continue;
-            }
-            let feed = feed.unwrap();
-
-            for (name, item) in feed {
-                match db.get(name.clone()) {
-                    Ok(Some(_)) => {
-                        debug!("Already in processed {}", name);
-                    }
-                    Ok(None) => {
-                        debug!("Processing {}", name);
-                        let ret = download_worker_cloned
-                            .add(name.to_owned(), item.clone())
-                            .await;
-                        if let Err(e) = ret {
-                            error!("Error adding download task: {}", e);
-                        }
-
-                        // Insert into database to avoid duplicate processing
-                        db.insert(name.clone()).unwrap_or_else(|e| {
-                            error!("Error inserting into database: {}", e);
-                        });
-                    }
-                    Err(e) => {
-                        error!("Error when accessing database {}", e);
-                    }
-                }
-            }
+    let filter = Arc::new(filter::Filter::new(&settings.rules).unwrap());
+    let preference = Arc::new(settings.preference);
+    let download_worker =
+        DownloadHandle::init(settings.download, settings.files, preference.clone())
+            .await
+            .unwrap();
 
-            tokio::time::sleep(std::time::Duration::from_secs(600)).await;
+    info!("Service started");
+    for subscribe in settings.subscribe {
+        if !subscribe.enabled {
+            info!("Feed {} is disabled", subscribe.name);
+            continue;
         }
-    });
+
+        tokio::spawn(worker::poll_feed(
+            subscribe,
+            download_worker.clone(),
+            filter.clone(),
+            preference.clone(),
+        ));
+    }
 
     let ctrl_c = async {
         signal::ctrl_c()
@@ -107,3 +103,84 @@ async fn main() {
     }
     info!("Service stopped");
 }
+
+async fn run_command(command: &str, args: &[String]) {
+    match command {
+        "invalidate-cache" => {
+            let Some(subject_id) = args.first().and_then(|s| s.parse::<u64>().ok()) else {
+                eprintln!("Usage: mikan-subscriber invalidate-cache <subject_id>");
+                std::process::exit(2);
+            };
+            let removed = store::Db::get_cache()
+                .unwrap()
+                .invalidate_subject(subject_id)
+                .unwrap();
+            if removed {
+                info!("Invalidated cache of subject {}", subject_id);
+            } else {
+                info!("No cache found for subject {}", subject_id);
+            }
+        }
+        "rejected" => {
+            let rejected = store::Db::get_rejected().unwrap().get_all().unwrap();
+            for (name, reason, timestamp) in rejected {
+                let time =
+                    chrono::DateTime::from_timestamp(timestamp as i64, 0).unwrap_or_default();
+                println!("{}\t{}\t{}", time.format("%Y-%m-%d %H:%M"), name, reason);
+            }
+        }
+        "onedrive-login" => {
+            let Some(name) = args.first() else {
+                eprintln!("Usage: mikan-subscriber onedrive-login <storage name>");
+                std::process::exit(2);
+            };
+            if let Err(e) = onedrive_login(name).await {
+                eprintln!("Error logging in {}: {}", name, e);
+                std::process::exit(1);
+            }
+            info!("Logged in {}", name);
+        }
+        _ => {
+            eprintln!("Unknown command: {}", command);
+            std::process::exit(2);
+        }
+    }
+}
+
+// 设备码登录，适合没有浏览器的环境，需要先停止正在运行的服务以释放数据库
+async fn onedrive_login(name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let settings = util::config::Settings::load_from_file("settings.json")?;
+    let _ = init_client(settings.proxy)?;
+    let tokens = store::Db::get_onedrive()?;
+    let sessions = store::Db::get_session()?;
+    let onedrive = settings
+        .storage
+        .into_iter()
+        .find_map(|s| match s {
+            util::config::Storage::Onedrive {
+                name: n,
+                client_id,
+                client_secret,
+                root,
+                api_type,
+            } if n == name => Some(target::Onedrive::new(
+                name,
+                &client_id,
+                &client_secret,
+                api_type,
+                root,
+                tokens.clone(),
+                sessions.clone(),
+            )),
+            _ => None,
+        })
+        .ok_or_else(|| format!("No Onedrive storage named {}", name))?;
+
+    let code = onedrive.request_device_code().await?;
+    println!(
+        "Open {} and enter the code {}",
+        code.verification_uri, code.user_code
+    );
+    onedrive.poll_device_code(&code).await?;
+    Ok(())
+}
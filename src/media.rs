@@ -0,0 +1,281 @@
+use std::path::{Path, PathBuf};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::util::{config, title};
+
+// 字幕文件名中的语言标记，如 .chs.ass、.zh-Hans.srt
+static LANGUAGE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[A-Za-z]{2,4}(?:[-_][A-Za-z]{2,4})*$").unwrap());
+
+/// 一个视频及属于它的外挂字幕
+#[derive(Debug, PartialEq)]
+pub struct Media {
+    pub video: PathBuf,
+    pub subtitles: Vec<PathBuf>,
+}
+
+/// 种子中需要上传的文件
+#[derive(Debug, Default, PartialEq)]
+pub struct Files {
+    pub media: Vec<Media>,
+    pub fonts: Vec<PathBuf>,
+}
+
+/// 递归列出目录中的所有文件，`path` 为文件时返回它本身
+pub async fn scan(path: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut dirs = vec![path.to_owned()];
+    while let Some(dir) = dirs.pop() {
+        if !dir.is_dir() {
+            if dir.exists() {
+                files.push(dir);
+            }
+            continue;
+        }
+
+        let Ok(mut entries) = tokio::fs::read_dir(&dir).await else {
+            tracing::error!("Error reading {}", dir.display());
+            continue;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            dirs.push(entry.path());
+        }
+    }
+
+    files.sort();
+    files
+}
+
+/// 按扩展名挑出视频、字幕和字体，并将字幕分配给对应的视频
+pub fn group(files: Vec<PathBuf>, config: &config::Files) -> Files {
+    let mut ret = Files::default();
+    let mut subtitles = Vec::new();
+    for file in files {
+        if excluded(&file, config) {
+            continue;
+        }
+        if has_extension(&file, &config.videos) {
+            ret.media.push(Media {
+                video: file,
+                subtitles: Vec::new(),
+            });
+        } else if has_extension(&file, &config.subtitles) {
+            subtitles.push(file);
+        } else if has_extension(&file, &config.fonts) {
+            ret.fonts.push(file);
+        }
+    }
+
+    for subtitle in subtitles {
+        match owner(&ret.media, &subtitle) {
+            Some(i) => ret.media[i].subtitles.push(subtitle),
+            None => tracing::warn!("No video found for subtitle {}", subtitle.display()),
+        }
+    }
+
+    ret
+}
+
+/// 种子中需要下载的文件序号，与 `group` 使用相同的规则；没有视频时返回 None，下载全部文件
+pub fn select(files: &[PathBuf], config: &config::Files) -> Option<Vec<usize>> {
+    let wanted = |file: &PathBuf| {
+        !excluded(file, config)
+            && [&config.videos, &config.subtitles, &config.fonts]
+                .into_iter()
+                .any(|extensions| has_extension(file, extensions))
+    };
+    if !files
+        .iter()
+        .any(|f| !excluded(f, config) && has_extension(f, &config.videos))
+    {
+        return None;
+    }
+
+    Some(
+        files
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| wanted(f))
+            .map(|(i, _)| i)
+            .collect(),
+    )
+}
+
+// 优先匹配文件名前缀最长的视频，其次是唯一的视频或集数相同的视频
+fn owner(media: &[Media], subtitle: &Path) -> Option<usize> {
+    let name = file_name(subtitle);
+    let prefix = media
+        .iter()
+        .enumerate()
+        .filter(|(_, m)| name.starts_with(&format!("{}.", file_stem(&m.video))))
+        .max_by_key(|(_, m)| file_stem(&m.video).len())
+        .map(|(i, _)| i);
+    if prefix.is_some() {
+        return prefix;
+    }
+
+    if media.len() == 1 {
+        return Some(0);
+    }
+
+    let episode = title::episode(name)?;
+    let mut same = media
+        .iter()
+        .enumerate()
+        .filter(|(_, m)| title::episode(file_name(&m.video)) == Some(episode));
+    match (same.next(), same.next()) {
+        (Some((i, _)), None) => Some(i),
+        _ => None,
+    }
+}
+
+/// 字幕上传后的路径，与视频同名并保留语言标记，如 `02 - name.chs.ass`
+pub fn subtitle_path(video: &Path, remote: &Path, subtitle: &Path) -> PathBuf {
+    let name = file_name(subtitle);
+    let video_stem = file_stem(video);
+    let suffix = match name.strip_prefix(video_stem) {
+        Some(suffix) if suffix.starts_with('.') => suffix.to_owned(),
+        _ => {
+            let stem = file_stem(subtitle);
+            let ext = subtitle
+                .extension()
+                .and_then(|e| e.to_str())
+                .unwrap_or_default();
+            match stem.rsplit_once('.') {
+                Some((_, lang)) if LANGUAGE.is_match(lang) => format!(".{}.{}", lang, ext),
+                _ => format!(".{}", ext),
+            }
+        }
+    };
+
+    remote.with_file_name(format!("{}{}", file_stem(remote), suffix))
+}
+
+/// 字体上传后的路径，放在视频所在目录的 fonts 文件夹
+pub fn font_path(remote: &Path, font: &Path) -> PathBuf {
+    remote.with_file_name("fonts").join(file_name(font))
+}
+
+// 任意一级目录在排除列表中
+fn excluded(path: &Path, config: &config::Files) -> bool {
+    path.parent().is_some_and(|parent| {
+        parent.components().any(|c| {
+            c.as_os_str().to_str().is_some_and(|dir| {
+                config
+                    .exclude_dirs
+                    .iter()
+                    .any(|x| x.eq_ignore_ascii_case(dir))
+            })
+        })
+    })
+}
+
+fn has_extension(path: &Path, extensions: &[String]) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| extensions.iter().any(|x| x.eq_ignore_ascii_case(e)))
+}
+
+fn file_name(path: &Path) -> &str {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default()
+}
+
+fn file_stem(path: &Path) -> &str {
+    path.file_stem()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn test_group() {
+        let files = paths(&[
+            "batch/[Group] Title - 01 [1080p].mkv",
+            "batch/[Group] Title - 01 [1080p].chs.ass",
+            "batch/[Group] Title - 01 [1080p].cht.ass",
+            "batch/[Group] Title - 02 [1080p].MKV",
+            "batch/subs/[Group] Title [02][CHS].srt",
+            "batch/Fonts/font.ttf",
+            "batch/scans/01.jpg",
+        ]);
+        let ret = group(files, &config::Files::default());
+
+        assert_eq!(
+            ret,
+            Files {
+                media: vec![
+                    Media {
+                        video: "batch/[Group] Title - 01 [1080p].mkv".into(),
+                        subtitles: paths(&[
+                            "batch/[Group] Title - 01 [1080p].chs.ass",
+                            "batch/[Group] Title - 01 [1080p].cht.ass",
+                        ]),
+                    },
+                    Media {
+                        video: "batch/[Group] Title - 02 [1080p].MKV".into(),
+                        subtitles: paths(&["batch/subs/[Group] Title [02][CHS].srt"]),
+                    },
+                ],
+                fonts: paths(&["batch/Fonts/font.ttf"]),
+            }
+        );
+    }
+
+    #[test]
+    fn test_select() {
+        let files = paths(&[
+            "batch/[Group] Title - 01 [1080p].mkv",
+            "batch/[Group] Title - 01 [1080p].chs.ass",
+            "batch/SPs/[Group] Title - NCOP [1080p].mkv",
+            "batch/Scans/01.jpg",
+            "batch/Fonts/font.ttf",
+            "batch/readme.txt",
+        ]);
+        assert_eq!(
+            select(&files, &config::Files::default()),
+            Some(vec![0, 1, 4])
+        );
+        assert_eq!(
+            select(&paths(&["album/01.flac"]), &config::Files::default()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_subtitle_path() {
+        let video = Path::new("[Group] Title - 01 [1080p].mkv");
+        let remote = Path::new("Title/Season 01/Title - S01E01 - Name.mkv");
+
+        assert_eq!(
+            subtitle_path(
+                video,
+                remote,
+                Path::new("[Group] Title - 01 [1080p].sc.ass")
+            ),
+            PathBuf::from("Title/Season 01/Title - S01E01 - Name.sc.ass")
+        );
+        assert_eq!(
+            subtitle_path(video, remote, Path::new("Title.S01E01.zh-Hans.srt")),
+            PathBuf::from("Title/Season 01/Title - S01E01 - Name.zh-Hans.srt")
+        );
+        assert_eq!(
+            subtitle_path(video, remote, Path::new("Title.S01E01.srt")),
+            PathBuf::from("Title/Season 01/Title - S01E01 - Name.srt")
+        );
+        assert_eq!(
+            font_path(remote, Path::new("Fonts/font.ttf")),
+            PathBuf::from("Title/Season 01/fonts/font.ttf")
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use std::fmt::Write as _;
+
+use crate::util::bangumi::{Episode, Subject};
+
+const HEADER: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#;
+// 只保留标记人数最多的几个标签作为类型
+const MAX_GENRES: usize = 10;
+
+/// Kodi 格式的 tvshow.nfo，Jellyfin、Emby、Plex（需要插件）都可以读取
+pub fn tvshow(subject: &Subject) -> String {
+    let mut nfo = format!("{}\n<tvshow>\n", HEADER);
+    push(&mut nfo, "title", title(subject));
+    push(&mut nfo, "originaltitle", &subject.name);
+    push(&mut nfo, "plot", &subject.summary);
+    if let Some(date) = subject.date.as_deref().filter(|d| !d.is_empty()) {
+        push(&mut nfo, "premiered", date);
+        if let Some(year) = date.split('-').next() {
+            push(&mut nfo, "year", year);
+        }
+    }
+    if subject.rating.score > 0.0 {
+        push(&mut nfo, "rating", &format!("{:.1}", subject.rating.score));
+    }
+
+    let mut tags = subject.tags.iter().collect::<Vec<_>>();
+    tags.sort_by_key(|t| std::cmp::Reverse(t.count));
+    for tag in tags.into_iter().take(MAX_GENRES) {
+        push(&mut nfo, "genre", &tag.name);
+    }
+
+    let _ = writeln!(
+        nfo,
+        "  <uniqueid type=\"bangumi\" default=\"true\">{}</uniqueid>",
+        subject.id
+    );
+    nfo.push_str("</tvshow>\n");
+    nfo
+}
+
+/// 单集的 nfo，文件名需要与视频相同
+pub fn episode(subject: &Subject, season: u32, number: u32, episode: Option<&Episode>) -> String {
+    let mut nfo = format!("{}\n<episodedetails>\n", HEADER);
+    let name = episode
+        .map(|e| {
+            if e.name_cn.is_empty() {
+                &e.name
+            } else {
+                &e.name_cn
+            }
+        })
+        .filter(|n| !n.is_empty());
+    match name {
+        Some(name) => push(&mut nfo, "title", name),
+        None => push(&mut nfo, "title", &format!("第{}集", number)),
+    }
+    push(&mut nfo, "showtitle", title(subject));
+    push(&mut nfo, "season", &season.to_string());
+    push(&mut nfo, "episode", &number.to_string());
+    if let Some(episode) = episode {
+        push(&mut nfo, "plot", &episode.desc);
+        push(&mut nfo, "aired", &episode.airdate);
+    }
+    nfo.push_str("</episodedetails>\n");
+    nfo
+}
+
+fn title(subject: &Subject) -> &str {
+    if subject.name_cn.is_empty() {
+        &subject.name
+    } else {
+        &subject.name_cn
+    }
+}
+
+// 空值不写入，避免覆盖媒体服务器自己刮削到的信息
+fn push(nfo: &mut String, tag: &str, value: &str) {
+    if value.is_empty() {
+        return;
+    }
+    let _ = writeln!(nfo, "  <{}>{}</{}>", tag, escape(value), tag);
+}
+
+fn escape(value: &str) -> String {
+    let mut ret = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            '\'' => ret.push_str("&apos;"),
+            _ => ret.push(c),
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::bangumi::{Rating, Tag};
+
+    use super::*;
+
+    fn subject() -> Subject {
+        Subject {
+            id: 400602,
+            name: "葬送のフリーレン".into(),
+            name_cn: "葬送的芙莉莲".into(),
+            summary: "勇者一行打倒魔王 & 凯旋归来".into(),
+            date: Some("2023-09-29".into()),
+            rating: Rating {
+                score: 8.9,
+                total: 1000,
+            },
+            tags: vec![
+                Tag {
+                    name: "奇幻".into(),
+                    count: 10,
+                },
+                Tag {
+                    name: "漫画改".into(),
+                    count: 20,
+                },
+            ],
+            images: None,
+        }
+    }
+
+    #[test]
+    fn test_tvshow() {
+        let nfo = tvshow(&subject());
+        assert!(nfo.contains("<title>葬送的芙莉莲</title>"));
+        assert!(nfo.contains("<originaltitle>葬送のフリーレン</originaltitle>"));
+        assert!(nfo.contains("<plot>勇者一行打倒魔王 &amp; 凯旋归来</plot>"));
+        assert!(nfo.contains("<year>2023</year>"));
+        assert!(nfo.contains("<rating>8.9</rating>"));
+        assert!(nfo.find("漫画改").unwrap() < nfo.find("奇幻").unwrap());
+        assert!(nfo.contains(r#"<uniqueid type="bangumi" default="true">400602</uniqueid>"#));
+    }
+
+    #[test]
+    fn test_episode() {
+        let ep = Episode {
+            name: "".into(),
+            name_cn: "".into(),
+            sort: 2,
+            ep: 2,
+            airdate: "2023-09-29".into(),
+            desc: "<desc>".into(),
+        };
+        let nfo = episode(&subject(), 1, 2, Some(&ep));
+        assert!(nfo.contains("<title>第2集</title>"));
+        assert!(nfo.contains("<showtitle>葬送的芙莉莲</showtitle>"));
+        assert!(nfo.contains("<episode>2</episode>"));
+        assert!(nfo.contains("<plot>&lt;desc&gt;</plot>"));
+        assert!(nfo.contains("<aired>2023-09-29</aired>"));
+    }
+}
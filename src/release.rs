@@ -0,0 +1,80 @@
+use crate::util::{config::Preference, title};
+
+/// 同一部动画同一集的标识，无法解析集数时返回 None
+pub fn key(title: &str, bangumi_tv_id: u64) -> Option<String> {
+    let episode = title::episode(title)?;
+    let season = title::season(title).unwrap_or(1);
+    Some(format!("{}:{}:{}", bangumi_tv_id, season, episode))
+}
+
+/// 按照偏好选出最合适的版本
+pub fn select<'a>(
+    preference: &Preference,
+    titles: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    titles
+        .into_iter()
+        .min_by_key(|title| (rank(preference, title), *title))
+}
+
+/// 是否比另一个版本更符合偏好，同样符合时为 false
+pub fn is_better(preference: &Preference, title: &str, than: &str) -> bool {
+    rank(preference, title) < rank(preference, than)
+}
+
+// 依次比较字幕组、分辨率、编码和字幕语言，不在偏好列表中的排在最后
+fn rank(preference: &Preference, title: &str) -> [usize; 4] {
+    let subgroup = title::subgroup(title).unwrap_or_default();
+    let subgroup = preference
+        .subgroups
+        .iter()
+        .position(|s| s.eq_ignore_ascii_case(subgroup))
+        .unwrap_or(preference.subgroups.len());
+
+    let resolution = title::resolution(title)
+        .and_then(|r| preference.resolutions.iter().position(|p| *p == r))
+        .unwrap_or(preference.resolutions.len());
+
+    let codec = title::codec(title)
+        .and_then(|c| {
+            preference
+                .codecs
+                .iter()
+                .position(|p| p.eq_ignore_ascii_case(c))
+        })
+        .unwrap_or(preference.codecs.len());
+
+    let language = preference
+        .languages
+        .iter()
+        .position(|l| title::contains_keyword(title, l))
+        .unwrap_or(preference.languages.len());
+
+    [subgroup, resolution, codec, language]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select() {
+        let preference = Preference {
+            subgroups: vec!["LoliHouse".into()],
+            resolutions: vec![1080],
+            codecs: vec!["HEVC".into()],
+            languages: vec!["简".into()],
+        };
+
+        let titles = [
+            "[Sakurato] Dungeon Meshi [17][AVC-8bit 1080p AAC][CHT].mp4",
+            "[LoliHouse] Dungeon Meshi - 17 [WebRip 1080p AVC AAC][繁日内封字幕]",
+            "[LoliHouse] Dungeon Meshi - 17 [WebRip 1080p HEVC-10bit AAC][简繁内封字幕]",
+        ];
+        assert_eq!(
+            titles.iter().map(|t| key(t, 395378)).collect::<Vec<_>>(),
+            vec![Some("395378:1:17".to_owned()); 3]
+        );
+        assert_eq!(select(&preference, titles), Some(titles[2]));
+    }
+}
@@ -0,0 +1,290 @@
+use std::sync::Arc;
+
+use redb::{Error, TableDefinition, TypeName, Value};
+
+use crate::util::{bangumi, llama};
+
+use super::Db;
+
+// 文件名的解析结果，值为结果和写入时间
+const DECODE: TableDefinition<String, (llama::ContentResponse, u64)> =
+    TableDefinition::new("decode_cache");
+// 番组的剧集列表，键为 subject_id
+const EPISODES: TableDefinition<u64, (bangumi::Episodes, u64)> =
+    TableDefinition::new("episodes_cache");
+// 番组的简介、评分等信息，键为 subject_id
+const SUBJECT: TableDefinition<u64, (bangumi::Subject, u64)> =
+    TableDefinition::new("subject_cache");
+
+const DECODE_EXPIRE_TIME: u64 = 60 * 60 * 24 * 30;
+// 剧集名称在放送期间可能会更新，缓存时间较短
+const EPISODES_EXPIRE_TIME: u64 = 60 * 60 * 24;
+
+#[derive(Debug)]
+pub struct Cache(pub Arc<Db>);
+
+impl Cache {
+    pub(super) fn init(&self) -> Result<(), Error> {
+        let write_txn = self.0.begin_write()?;
+        write_txn.open_table(DECODE)?;
+        write_txn.open_table(EPISODES)?;
+        write_txn.open_table(SUBJECT)?;
+        write_txn.commit()?;
+
+        tokio::spawn(async move {
+            // sleep 随机时间，避免同时清理
+            tokio::time::sleep(tokio::time::Duration::from_secs(rand::random::<u64>() % 60)).await;
+            loop {
+                Db::get_cache().unwrap().clear_expire().unwrap_or_else(|e| {
+                    tracing::error!("Error clearing expired cache: {}", e);
+                });
+
+                tokio::time::sleep(tokio::time::Duration::from_secs(60 * 60 * 24)).await;
+            }
+        });
+
+        Ok(())
+    }
+
+    pub fn insert_decode(&self, name: &str, content: llama::ContentResponse) -> Result<(), Error> {
+        let write_txn = self.0.begin_write()?;
+        {
+            let mut table = write_txn.open_table(DECODE)?;
+            table.insert(name.to_string(), (content, now()))?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    pub fn get_decode(&self, name: &str) -> Result<Option<llama::ContentResponse>, Error> {
+        let read_txn = self.0.begin_read()?;
+        let table = read_txn.open_table(DECODE)?;
+        let content = table
+            .get(name.to_string())?
+            .map(|s| s.value())
+            .filter(|(_, timestamp)| now() - timestamp < DECODE_EXPIRE_TIME)
+            .map(|(content, _)| content);
+
+        Ok(content)
+    }
+
+    pub fn insert_episodes(
+        &self,
+        subject_id: u64,
+        episodes: bangumi::Episodes,
+    ) -> Result<(), Error> {
+        let write_txn = self.0.begin_write()?;
+        {
+            let mut table = write_txn.open_table(EPISODES)?;
+            table.insert(subject_id, (episodes, now()))?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    pub fn get_episodes(&self, subject_id: u64) -> Result<Option<bangumi::Episodes>, Error> {
+        let read_txn = self.0.begin_read()?;
+        let table = read_txn.open_table(EPISODES)?;
+        let episodes = table
+            .get(subject_id)?
+            .map(|s| s.value())
+            .filter(|(_, timestamp)| now() - timestamp < EPISODES_EXPIRE_TIME)
+            .map(|(episodes, _)| episodes);
+
+        Ok(episodes)
+    }
+
+    pub fn insert_subject(&self, subject: bangumi::Subject) -> Result<(), Error> {
+        let write_txn = self.0.begin_write()?;
+        {
+            let mut table = write_txn.open_table(SUBJECT)?;
+            table.insert(subject.id, (subject, now()))?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    pub fn get_subject(&self, subject_id: u64) -> Result<Option<bangumi::Subject>, Error> {
+        let read_txn = self.0.begin_read()?;
+        let table = read_txn.open_table(SUBJECT)?;
+        let subject = table
+            .get(subject_id)?
+            .map(|s| s.value())
+            .filter(|(_, timestamp)| now() - timestamp < EPISODES_EXPIRE_TIME)
+            .map(|(subject, _)| subject);
+
+        Ok(subject)
+    }
+
+    /// 删除某个番组的缓存，剧集名称更新后使用
+    pub fn invalidate_subject(&self, subject_id: u64) -> Result<bool, Error> {
+        let write_txn = self.0.begin_write()?;
+        let removed = {
+            let mut table = write_txn.open_table(EPISODES)?;
+            let episodes = table.remove(subject_id)?.is_some();
+            let mut table = write_txn.open_table(SUBJECT)?;
+            let subject = table.remove(subject_id)?.is_some();
+            episodes || subject
+        };
+        write_txn.commit()?;
+        Ok(removed)
+    }
+
+    pub fn clear_expire(&self) -> Result<(), Error> {
+        let write_txn = self.0.begin_write()?;
+        {
+            let mut table = write_txn.open_table(DECODE)?;
+            table.retain(|_, (_, timestamp)| now() - timestamp < DECODE_EXPIRE_TIME)?;
+
+            let mut table = write_txn.open_table(EPISODES)?;
+            table.retain(|_, (_, timestamp)| now() - timestamp < EPISODES_EXPIRE_TIME)?;
+
+            let mut table = write_txn.open_table(SUBJECT)?;
+            table.retain(|_, (_, timestamp)| now() - timestamp < EPISODES_EXPIRE_TIME)?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+impl Value for llama::ContentResponse {
+    type SelfType<'a>
+        = Self
+    where
+        Self: 'a;
+    type AsBytes<'a>
+        = Vec<u8>
+    where
+        Self: 'a;
+
+    fn fixed_width() -> Option<usize> {
+        None
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'a,
+        Self: 'b,
+    {
+        bincode::serde::encode_to_vec(value, bincode::config::legacy()).unwrap()
+    }
+
+    fn type_name() -> redb::TypeName {
+        TypeName::new("content_response")
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        bincode::serde::decode_from_slice(data, bincode::config::legacy())
+            .unwrap()
+            .0
+    }
+}
+
+impl Value for bangumi::Episodes {
+    type SelfType<'a>
+        = Self
+    where
+        Self: 'a;
+    type AsBytes<'a>
+        = Vec<u8>
+    where
+        Self: 'a;
+
+    fn fixed_width() -> Option<usize> {
+        None
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'a,
+        Self: 'b,
+    {
+        bincode::serde::encode_to_vec(value, bincode::config::legacy()).unwrap()
+    }
+
+    fn type_name() -> redb::TypeName {
+        TypeName::new("bangumi_episodes")
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        bincode::serde::decode_from_slice(data, bincode::config::legacy())
+            .unwrap()
+            .0
+    }
+}
+
+impl Value for bangumi::Subject {
+    type SelfType<'a>
+        = Self
+    where
+        Self: 'a;
+    type AsBytes<'a>
+        = Vec<u8>
+    where
+        Self: 'a;
+
+    fn fixed_width() -> Option<usize> {
+        None
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'a,
+        Self: 'b,
+    {
+        bincode::serde::encode_to_vec(value, bincode::config::legacy()).unwrap()
+    }
+
+    fn type_name() -> redb::TypeName {
+        TypeName::new("bangumi_subject")
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        bincode::serde::decode_from_slice(data, bincode::config::legacy())
+            .unwrap()
+            .0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::store::Db;
+    use crate::util::bangumi::{Episode, Episodes};
+
+    #[tokio::test]
+    async fn test_invalidate_subject() {
+        let cache = Db::get_cache().unwrap();
+        let episodes = Episodes {
+            data: vec![Episode {
+                name: "name".to_string(),
+                name_cn: "名称".to_string(),
+                sort: 1,
+                ep: 1,
+                airdate: "2023-09-29".to_string(),
+                desc: String::new(),
+            }],
+        };
+        cache.insert_episodes(u64::MAX, episodes).unwrap();
+        assert!(cache.get_episodes(u64::MAX).unwrap().is_some());
+
+        assert!(cache.invalidate_subject(u64::MAX).unwrap());
+        assert!(cache.get_episodes(u64::MAX).unwrap().is_none());
+        assert!(!cache.invalidate_subject(u64::MAX).unwrap());
+    }
+}
@@ -4,9 +4,12 @@ use chrono::NaiveDate;
 use redb::{Error, ReadableTable, TableDefinition, TypeName, Value};
 use serde::{Deserialize, Serialize};
 
-use super::Db;
+use super::{legacy, Db};
 
-const TABLE: TableDefinition<String, Task> = TableDefinition::new("tasks");
+// 以 JSON 保存，之后增加的字段需要有默认值，旧记录缺少时使用默认值
+const TABLE: TableDefinition<String, Task> = TableDefinition::new("tasks_v2");
+// 最初以 bincode 按字段顺序保存的任务，增加字段后无法解析，启动时迁移到 TABLE
+const LEGACY_TABLE: &str = "tasks";
 
 #[derive(Debug)]
 pub struct Tasks(pub Arc<Db>);
@@ -19,10 +22,90 @@ pub struct Task {
     pub added_at: u64,
     pub state: TaskState,
     pub bangumi_id: u64,
+    /// 来自哪个订阅源，迁移的旧任务为空，上传到所有存储
+    #[serde(default)]
+    pub feed: String,
+    /// 被该修订版本替代的旧任务
+    #[serde(default)]
+    pub supersedes: Option<String>,
+    /// 每个上传目标的上传状态
+    #[serde(default)]
+    pub uploads: HashMap<String, UploadState>,
+    /// 只下载种子中的这些文件，None 表示全部下载
+    #[serde(default)]
+    pub only_files: Option<Vec<usize>>,
+    /// 下载失败的次数
+    #[serde(default)]
+    pub attempts: u32,
+    /// 最近一次下载失败的原因
+    #[serde(default)]
+    pub last_error: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum TaskState {
+    Pending,
+    Downloading,
+    Downloaded {
+        file_path: PathBuf,
+        info_hash: String,
+    },
+    Finished {
+        file_path: PathBuf,
+        info_hash: String,
+        finish_time: u64,
+    },
+    /// 下载失败，到达 retry_at 后重试
+    Blocked {
+        retry_at: u64,
+    },
+    /// 超过重试次数且没有其他版本可以下载
+    GaveUp,
+    /// 下载完成后被修订版本取消，等待正在进行的上传结束后删除
+    Cancelled {
+        file_path: PathBuf,
+        info_hash: String,
+    },
+    /// 部分上传目标超过重试次数，其余已上传
+    Partial {
+        file_path: PathBuf,
+        info_hash: String,
+        finish_time: u64,
+        missing: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum UploadState {
+    Uploaded,
+    Failed {
+        attempts: u32,
+        error: String,
+        retry_at: u64,
+    },
+    /// 超过最大重试次数，不再上传
+    GaveUp {
+        attempts: u32,
+        error: String,
+    },
+    /// 已上传，但目标不提供校验值只比较了大小
+    Unverified,
+}
+
+// 最初版本的任务格式，只用于迁移
+#[derive(Debug, Serialize, Deserialize)]
+struct TaskV1 {
+    url: String,
+    anime_title: String,
+    weekday: String,
+    air_date: NaiveDate,
+    added_at: u64,
+    state: TaskStateV1,
+    bangumi_id: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum TaskStateV1 {
     Pending,
     Downloading,
     Downloaded {
@@ -37,10 +120,74 @@ pub enum TaskState {
     Blocked,
 }
 
+#[derive(Debug)]
+struct LegacyTask;
+
+impl legacy::Legacy for LegacyTask {
+    const TYPE_NAME: &'static str = "task";
+}
+
+impl From<TaskV1> for Task {
+    fn from(task: TaskV1) -> Self {
+        let (state, attempts) = match task.state {
+            TaskStateV1::Pending => (TaskState::Pending, 0),
+            TaskStateV1::Downloading => (TaskState::Downloading, 0),
+            TaskStateV1::Downloaded {
+                file_path,
+                info_hash,
+            } => (
+                TaskState::Downloaded {
+                    file_path,
+                    info_hash,
+                },
+                0,
+            ),
+            TaskStateV1::Finished {
+                file_path,
+                info_hash,
+                finish_time,
+            } => (
+                TaskState::Finished {
+                    file_path,
+                    info_hash,
+                    finish_time,
+                },
+                0,
+            ),
+            // 旧版本超时后不再重试，迁移后立即重试一次
+            TaskStateV1::Blocked => (TaskState::Blocked { retry_at: 0 }, 1),
+        };
+
+        Self {
+            url: task.url,
+            anime_title: task.anime_title,
+            weekday: task.weekday,
+            air_date: task.air_date,
+            added_at: task.added_at,
+            state,
+            bangumi_id: task.bangumi_id,
+            feed: String::new(),
+            supersedes: None,
+            uploads: HashMap::new(),
+            only_files: None,
+            attempts,
+            last_error: None,
+        }
+    }
+}
+
 impl Tasks {
     pub(super) fn init(&self) -> Result<(), Error> {
         let write_txn = self.0.begin_write()?;
-        write_txn.open_table(TABLE)?;
+        let migrated = legacy::drain::<LegacyTask, _>(&write_txn, LEGACY_TABLE, |data| {
+            legacy::decode_bincode::<TaskV1>(data).map(Task::from)
+        })?;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            for (name, task) in migrated {
+                table.insert(name, Some(task))?;
+            }
+        }
         write_txn.commit()?;
         Ok(())
     }
@@ -49,10 +196,10 @@ impl Tasks {
         let write_txn = self.0.begin_write()?;
         {
             let mut table = write_txn.open_table(TABLE)?;
-            if let Some(old_task) = table.get(name.clone())? {
-                task.added_at = old_task.value().added_at;
+            if let Some(old_task) = table.get(name.clone())?.and_then(|s| s.value()) {
+                task.added_at = old_task.added_at;
             }
-            table.insert(name, task)?;
+            table.insert(name, Some(task))?;
         }
         write_txn.commit()?;
         Ok(())
@@ -62,22 +209,80 @@ impl Tasks {
         let write_txn = self.0.begin_write()?;
         {
             let mut table = write_txn.open_table(TABLE)?;
-            let old_task = table.get(name.clone())?.map(|s| s.value().to_owned());
+            let old_task = table.get(name.clone())?.and_then(|s| s.value());
             if let Some(mut task) = old_task {
                 task.state = state;
-                table.insert(name, task)?;
+                table.insert(name, Some(task))?;
+            }
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    pub fn update_upload(
+        &self,
+        name: String,
+        target: String,
+        state: UploadState,
+    ) -> Result<(), Error> {
+        let write_txn = self.0.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            let old_task = table.get(name.clone())?.and_then(|s| s.value());
+            if let Some(mut task) = old_task {
+                task.uploads.insert(target, state);
+                table.insert(name, Some(task))?;
+            }
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// 记录一次下载失败，`next` 根据累计的失败次数返回新的状态
+    pub fn fail(
+        &self,
+        name: String,
+        error: String,
+        next: impl FnOnce(u32) -> TaskState,
+    ) -> Result<(), Error> {
+        let write_txn = self.0.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            let old_task = table.get(name.clone())?.and_then(|s| s.value());
+            if let Some(mut task) = old_task {
+                task.attempts += 1;
+                task.last_error = Some(error);
+                task.state = next(task.attempts);
+                table.insert(name, Some(task))?;
+            }
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    pub fn update_only_files(
+        &self,
+        name: String,
+        only_files: Option<Vec<usize>>,
+    ) -> Result<(), Error> {
+        let write_txn = self.0.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            let old_task = table.get(name.clone())?.and_then(|s| s.value());
+            if let Some(mut task) = old_task {
+                task.only_files = only_files;
+                table.insert(name, Some(task))?;
             }
         }
         write_txn.commit()?;
         Ok(())
     }
 
-    #[allow(dead_code)]
     pub fn get(&self, name: String) -> Result<Option<Task>, Error> {
         let read_txn = self.0.begin_read()?;
         let table = read_txn.open_table(TABLE)?;
         let task = table.get(name)?;
-        let task = task.map(|s| s.value().to_owned());
+        let task = task.and_then(|s| s.value());
 
         Ok(task)
     }
@@ -102,14 +307,13 @@ impl Tasks {
         let mut iter = table.range::<String>(..)?;
         let mut result = HashMap::new();
         while let Some(Ok((key, value))) = iter.next() {
-            if cmp(value.value().state) {
-                result.insert(key.value().to_owned(), value.value().to_owned());
+            if let Some(task) = value.value().filter(|task| cmp(task.state.clone())) {
+                result.insert(key.value().to_owned(), task);
             }
         }
         Ok(result)
     }
 
-    #[allow(dead_code)]
     pub fn get_all(&self) -> Result<HashMap<String, Task>, Error> {
         let read_txn = self.0.begin_read()?;
         let table = read_txn.open_table(TABLE)?;
@@ -117,17 +321,22 @@ impl Tasks {
         let mut iter = table.range::<String>(..)?;
         let mut result = HashMap::new();
         while let Some(Ok((key, value))) = iter.next() {
-            result.insert(key.value().to_owned(), value.value().to_owned());
+            if let Some(task) = value.value() {
+                result.insert(key.value().to_owned(), task);
+            }
         }
         Ok(result)
     }
 }
 
+// 无法解析的记录读取为 None，不影响其他任务
 impl Value for Task {
-    type SelfType<'a> =  Self
+    type SelfType<'a>
+        = Option<Self>
     where
         Self: 'a;
-    type AsBytes<'a> = Vec<u8>
+    type AsBytes<'a>
+        = Vec<u8>
     where
         Self: 'a;
 
@@ -140,23 +349,28 @@ impl Value for Task {
         Self: 'a,
         Self: 'b,
     {
-        bincode::serde::encode_to_vec(value, bincode::config::legacy()).unwrap()
+        serde_json::to_vec(value).unwrap()
     }
 
     fn type_name() -> redb::TypeName {
-        TypeName::new("task")
+        TypeName::new("task_v2")
     }
 
     fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
     where
         Self: 'a,
     {
-        bincode::serde::decode_from_slice(data, bincode::config::legacy()).unwrap().0
+        serde_json::from_slice(data)
+            .map_err(|e| tracing::error!("Error decoding task: {}", e))
+            .ok()
     }
 }
 
 #[cfg(test)]
 mod test {
+    use redb::TableHandle;
+
+    use super::*;
     use crate::store::Db;
 
     #[test]
@@ -167,4 +381,92 @@ mod test {
         let all = db.get_all().unwrap();
         dbg!(all);
     }
+
+    // 最初版本以 bincode 保存的任务迁移后保留原有字段，新字段使用默认值
+    #[test]
+    fn test_migrate_v1() {
+        let path = std::env::temp_dir().join("mikan-subscriber-test-migrate-task.db");
+        let _ = std::fs::remove_file(&path);
+        let db = Arc::new(Db(redb::Database::create(&path).unwrap()));
+
+        let task = |state| TaskV1 {
+            url: "magnet:?xt=urn:btih:5d9140ed25be2cff3b981566792b668ab6976f58".into(),
+            anime_title: "Dungeon Meshi".into(),
+            weekday: "木曜日".into(),
+            air_date: NaiveDate::from_ymd_opt(2024, 1, 4).unwrap(),
+            added_at: 1704326400,
+            state,
+            bangumi_id: 395378,
+        };
+        let finished = task(TaskStateV1::Finished {
+            file_path: "tmp/Dungeon Meshi - 17.mkv".into(),
+            info_hash: "5d9140ed25be2cff3b981566792b668ab6976f58".into(),
+            finish_time: 1704330000,
+        });
+        let encode = |task| bincode::serde::encode_to_vec(task, bincode::config::legacy()).unwrap();
+
+        let legacy = TableDefinition::<String, legacy::Raw<LegacyTask>>::new(LEGACY_TABLE);
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut table = write_txn.open_table(legacy).unwrap();
+            table
+                .insert("finished".to_owned(), encode(&finished).as_slice())
+                .unwrap();
+            table
+                .insert(
+                    "blocked".to_owned(),
+                    encode(&task(TaskStateV1::Blocked)).as_slice(),
+                )
+                .unwrap();
+            table.insert("broken".to_owned(), &[1, 2, 3][..]).unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        let tasks = Tasks(db.clone());
+        tasks.init().unwrap();
+        let all = tasks.get_all().unwrap();
+        assert_eq!(all.len(), 2);
+
+        let task = &all["finished"];
+        assert_eq!(task.url, finished.url);
+        assert_eq!(task.air_date, finished.air_date);
+        assert_eq!(task.bangumi_id, 395378);
+        assert_eq!(task.feed, "");
+        assert!(task.uploads.is_empty());
+        assert!(matches!(
+            task.state,
+            TaskState::Finished {
+                finish_time: 1704330000,
+                ..
+            }
+        ));
+        let task = &all["blocked"];
+        assert!(matches!(task.state, TaskState::Blocked { retry_at: 0 }));
+        assert_eq!(task.attempts, 1);
+
+        let read_txn = db.begin_read().unwrap();
+        assert!(read_txn
+            .list_tables()
+            .unwrap()
+            .all(|t| t.name() != LEGACY_TABLE));
+        drop(read_txn);
+
+        // 再次启动时没有需要迁移的记录
+        tasks.init().unwrap();
+        assert_eq!(tasks.get_all().unwrap().len(), 2);
+
+        drop(tasks);
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_decode() {
+        let json = r#"{"url":"url","anime_title":"title","weekday":"","air_date":"2024-01-04","added_at":0,"state":"Pending","bangumi_id":1}"#;
+        let task = Task::from_bytes(json.as_bytes()).unwrap();
+        assert!(matches!(task.state, TaskState::Pending));
+        assert_eq!(task.feed, "");
+
+        assert!(Task::from_bytes(b"not a task").is_none());
+    }
 }
@@ -0,0 +1,139 @@
+use std::{path::PathBuf, sync::Arc};
+
+use redb::{Error, ReadableTable, TableDefinition, TypeName, Value};
+use serde::{Deserialize, Serialize};
+
+use super::Db;
+
+// 任务的历史记录，任务被删除后仍然保留
+const TABLE: TableDefinition<String, History> = TableDefinition::new("history");
+
+#[derive(Debug)]
+pub struct Histories(pub Arc<Db>);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct History {
+    pub events: Vec<Event>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    pub time: u64,
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum EventKind {
+    Uploaded {
+        target: String,
+        path: PathBuf,
+    },
+    Superseded {
+        by: String,
+    },
+    /// 远程已经存在大小相同的文件，没有上传
+    Skipped {
+        target: String,
+        path: PathBuf,
+    },
+    /// 超过最大重试次数后放弃上传
+    UploadFailed {
+        target: String,
+        error: String,
+    },
+    /// 超过最大重试次数后放弃下载，alternate 为改为下载的其他版本
+    DownloadFailed {
+        error: String,
+        alternate: Option<String>,
+    },
+}
+
+impl History {
+    /// 上传过或已经存在而跳过的所有文件及其上传目标
+    pub fn uploaded(&self) -> Vec<(String, PathBuf)> {
+        let mut uploaded: Vec<(String, PathBuf)> = Vec::new();
+        for event in &self.events {
+            if let EventKind::Uploaded { target, path } | EventKind::Skipped { target, path } =
+                &event.kind
+            {
+                let item = (target.clone(), path.clone());
+                if !uploaded.contains(&item) {
+                    uploaded.push(item);
+                }
+            }
+        }
+        uploaded
+    }
+}
+
+impl Histories {
+    pub(super) fn init(&self) -> Result<(), Error> {
+        let write_txn = self.0.begin_write()?;
+        write_txn.open_table(TABLE)?;
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    pub fn push(&self, name: String, kind: EventKind) -> Result<(), Error> {
+        let write_txn = self.0.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            let mut history = table
+                .get(name.clone())?
+                .map(|s| s.value().to_owned())
+                .unwrap_or_default();
+            history.events.push(Event {
+                time: chrono::Utc::now().timestamp() as u64,
+                kind,
+            });
+            table.insert(name, history)?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    pub fn get(&self, name: String) -> Result<History, Error> {
+        let read_txn = self.0.begin_read()?;
+        let table = read_txn.open_table(TABLE)?;
+        let history = table.get(name)?;
+        let history = history.map(|s| s.value().to_owned()).unwrap_or_default();
+
+        Ok(history)
+    }
+}
+
+impl Value for History {
+    type SelfType<'a>
+        = Self
+    where
+        Self: 'a;
+    type AsBytes<'a>
+        = Vec<u8>
+    where
+        Self: 'a;
+
+    fn fixed_width() -> Option<usize> {
+        None
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'a,
+        Self: 'b,
+    {
+        bincode::serde::encode_to_vec(value, bincode::config::legacy()).unwrap()
+    }
+
+    fn type_name() -> redb::TypeName {
+        TypeName::new("history")
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        bincode::serde::decode_from_slice(data, bincode::config::legacy())
+            .unwrap()
+            .0
+    }
+}
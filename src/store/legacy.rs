@@ -0,0 +1,83 @@
+use std::{fmt::Debug, marker::PhantomData};
+
+use redb::{Error, ReadableTable, TableDefinition, TableHandle, TypeName, Value, WriteTransaction};
+use serde::de::DeserializeOwned;
+
+/// 旧表中值的类型名，打开旧表时需要与写入时一致
+pub(super) trait Legacy: Debug + 'static {
+    const TYPE_NAME: &'static str;
+}
+
+/// 以原始字节读取旧表，不关心其中的格式
+#[derive(Debug)]
+pub(super) struct Raw<L>(PhantomData<L>);
+
+impl<L: Legacy> Value for Raw<L> {
+    type SelfType<'a>
+        = &'a [u8]
+    where
+        Self: 'a;
+    type AsBytes<'a>
+        = &'a [u8]
+    where
+        Self: 'a;
+
+    fn fixed_width() -> Option<usize> {
+        None
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'a,
+        Self: 'b,
+    {
+        value
+    }
+
+    fn type_name() -> TypeName {
+        TypeName::new(L::TYPE_NAME)
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        data
+    }
+}
+
+/// 按字段顺序解析 bincode，有剩余的字节说明格式不同
+pub(super) fn decode_bincode<T: DeserializeOwned>(data: &[u8]) -> Option<T> {
+    match bincode::serde::decode_from_slice(data, bincode::config::legacy()) {
+        Ok((value, len)) if len == data.len() => Some(value),
+        _ => None,
+    }
+}
+
+/// 读出旧表中能够解析的记录并删除旧表，无法解析的记录只写入日志
+pub(super) fn drain<L: Legacy, T>(
+    txn: &WriteTransaction,
+    name: &str,
+    decode: impl Fn(&[u8]) -> Option<T>,
+) -> Result<Vec<(String, T)>, Error> {
+    if !txn.list_tables()?.any(|table| table.name() == name) {
+        return Ok(Vec::new());
+    }
+
+    let definition = TableDefinition::<String, Raw<L>>::new(name);
+    let mut rows = Vec::new();
+    {
+        let table = txn.open_table(definition)?;
+        for row in table.iter()? {
+            let (key, value) = row?;
+            match decode(value.value()) {
+                Some(value) => rows.push((key.value(), value)),
+                None => tracing::error!("Dropping undecodable record {} in {}", key.value(), name),
+            }
+        }
+    }
+    txn.delete_table(definition)?;
+
+    tracing::info!("Migrated {} records from {}", rows.len(), name);
+    Ok(rows)
+}
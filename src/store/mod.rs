@@ -1,19 +1,31 @@
 use redb::Error;
 use std::{
     ops::Deref,
+    path::Path,
     sync::{Arc, OnceLock},
 };
 
 const DB_PATH: &str = "store.db";
 
 mod anime;
+mod cache;
 mod download;
 mod episode;
+mod history;
+mod legacy;
 mod onedrive;
+mod rejected;
+mod release;
+mod session;
 mod subscribe;
 
 pub use download::Task as DownloadTask;
 pub use download::TaskState as DownloadTaskState;
+pub use download::UploadState;
+pub use history::EventKind as HistoryEvent;
+pub use onedrive::Onedrive as OnedriveTokens;
+pub use session::Session as UploadSession;
+pub use session::Sessions as UploadSessions;
 
 static DB: OnceLock<Arc<Db>> = OnceLock::new();
 static SUBSCRIBE: OnceLock<Arc<subscribe::Subscribe>> = OnceLock::new();
@@ -21,16 +33,25 @@ static DOWNLOAD: OnceLock<Arc<download::Tasks>> = OnceLock::new();
 static ONEDRIVE: OnceLock<Arc<onedrive::Onedrive>> = OnceLock::new();
 static ANIME: OnceLock<Arc<anime::Anime>> = OnceLock::new();
 static EPISODE: OnceLock<Arc<episode::Episode>> = OnceLock::new();
+static REJECTED: OnceLock<Arc<rejected::Rejected>> = OnceLock::new();
+static RELEASE: OnceLock<Arc<release::Releases>> = OnceLock::new();
+static HISTORY: OnceLock<Arc<history::Histories>> = OnceLock::new();
+static CACHE: OnceLock<Arc<cache::Cache>> = OnceLock::new();
+static SESSION: OnceLock<Arc<session::Sessions>> = OnceLock::new();
 
 #[derive(Debug)]
 pub struct Db(redb::Database);
 
 impl Db {
+    pub fn open(path: impl AsRef<Path>) -> Result<Arc<Self>, Error> {
+        Ok(Arc::new(Self(redb::Database::create(path)?)))
+    }
+
     pub fn get_db() -> Result<Arc<Self>, Error> {
         if let Some(db) = DB.get() {
             return Ok(db.clone());
         }
-        let db = Arc::new(Self(redb::Database::create(DB_PATH)?));
+        let db = Self::open(DB_PATH)?;
 
         DB.set(db.clone()).unwrap();
         Ok(db)
@@ -65,8 +86,7 @@ impl Db {
             Ok(onedrive.clone())
         } else {
             let db = Self::get_db()?;
-            let onedrive = Arc::new(onedrive::Onedrive(db));
-            onedrive.init()?;
+            let onedrive = Arc::new(onedrive::Onedrive::open(db)?);
             ONEDRIVE.set(onedrive.clone()).unwrap();
             Ok(onedrive)
         }
@@ -95,6 +115,66 @@ impl Db {
             Ok(episode)
         }
     }
+
+    pub fn get_rejected() -> Result<Arc<rejected::Rejected>, Error> {
+        if let Some(rejected) = REJECTED.get() {
+            Ok(rejected.clone())
+        } else {
+            let db = Self::get_db()?;
+            let rejected = Arc::new(rejected::Rejected(db));
+            rejected.init()?;
+            REJECTED.set(rejected.clone()).unwrap();
+            Ok(rejected)
+        }
+    }
+
+    pub fn get_release() -> Result<Arc<release::Releases>, Error> {
+        if let Some(release) = RELEASE.get() {
+            Ok(release.clone())
+        } else {
+            let db = Self::get_db()?;
+            let release = Arc::new(release::Releases(db));
+            release.init()?;
+            RELEASE.set(release.clone()).unwrap();
+            Ok(release)
+        }
+    }
+
+    pub fn get_history() -> Result<Arc<history::Histories>, Error> {
+        if let Some(history) = HISTORY.get() {
+            Ok(history.clone())
+        } else {
+            let db = Self::get_db()?;
+            let history = Arc::new(history::Histories(db));
+            history.init()?;
+            HISTORY.set(history.clone()).unwrap();
+            Ok(history)
+        }
+    }
+
+    pub fn get_cache() -> Result<Arc<cache::Cache>, Error> {
+        if let Some(cache) = CACHE.get() {
+            Ok(cache.clone())
+        } else {
+            let db = Self::get_db()?;
+            let cache = Arc::new(cache::Cache(db));
+            cache.init()?;
+            CACHE.set(cache.clone()).unwrap();
+            Ok(cache)
+        }
+    }
+
+    pub fn get_session() -> Result<Arc<session::Sessions>, Error> {
+        if let Some(session) = SESSION.get() {
+            Ok(session.clone())
+        } else {
+            let db = Self::get_db()?;
+            let session = Arc::new(session::Sessions::open(db)?);
+            session.init();
+            SESSION.set(session.clone()).unwrap();
+            Ok(session)
+        }
+    }
 }
 
 impl Deref for Db {
@@ -10,11 +10,11 @@ const ONEDRIVE: TableDefinition<String, String> = TableDefinition::new("onedrive
 pub struct Onedrive(pub Arc<Db>);
 
 impl Onedrive {
-    pub(super) fn init(&self) -> Result<(), Error> {
-        let write_txn = self.0.begin_write()?;
+    pub fn open(db: Arc<Db>) -> Result<Self, Error> {
+        let write_txn = db.begin_write()?;
         write_txn.open_table(ONEDRIVE)?;
         write_txn.commit()?;
-        Ok(())
+        Ok(Self(db))
     }
 
     pub fn insert_refresh_token(&self, token: String, name: String) -> Result<(), Error> {
@@ -35,4 +35,14 @@ impl Onedrive {
 
         Ok(token)
     }
+
+    pub fn remove_refresh_token(&self, name: String) -> Result<(), Error> {
+        let write_txn = self.0.begin_write()?;
+        {
+            let mut table = write_txn.open_table(ONEDRIVE)?;
+            table.remove(name)?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
 }
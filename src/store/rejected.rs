@@ -0,0 +1,91 @@
+use std::sync::Arc;
+
+use redb::{Error, TableDefinition};
+
+use super::Db;
+
+// 被过滤规则拒绝的剧集，值为拒绝原因和最后一次被拒绝的时间
+const TABLE: TableDefinition<String, (String, u64)> = TableDefinition::new("rejected");
+
+// 每次轮询都会刷新仍在订阅源中的剧集，超过该时间未刷新说明已经不在订阅源中
+const EXPIRE_TIME: u64 = 60 * 60 * 24 * 30;
+
+#[derive(Debug)]
+pub struct Rejected(pub Arc<Db>);
+
+impl Rejected {
+    pub(super) fn init(&self) -> Result<(), Error> {
+        let write_txn = self.0.begin_write()?;
+        write_txn.open_table(TABLE)?;
+        write_txn.commit()?;
+
+        tokio::spawn(async move {
+            // sleep 随机时间，避免同时清理
+            tokio::time::sleep(tokio::time::Duration::from_secs(rand::random::<u64>() % 60)).await;
+            loop {
+                Db::get_rejected()
+                    .unwrap()
+                    .clear_expire()
+                    .unwrap_or_else(|e| {
+                        tracing::error!("Error clearing expired rejected: {}", e);
+                    });
+
+                tokio::time::sleep(tokio::time::Duration::from_secs(60 * 60 * 24)).await;
+            }
+        });
+
+        Ok(())
+    }
+
+    pub fn insert(&self, name: String, reason: String) -> Result<(), Error> {
+        let write_txn = self.0.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            let timestamp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            table.insert(name, (reason, timestamp))?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// 返回剧集名、拒绝原因和最后一次被拒绝的时间
+    pub fn get_all(&self) -> Result<Vec<(String, String, u64)>, Error> {
+        let read_txn = self.0.begin_read()?;
+        let table = read_txn.open_table(TABLE)?;
+
+        let mut iter = table.range::<String>(..)?;
+        let mut result = Vec::new();
+        while let Some(Ok((key, value))) = iter.next() {
+            let (reason, timestamp) = value.value();
+            result.push((key.value().to_owned(), reason, timestamp));
+        }
+        Ok(result)
+    }
+
+    pub fn remove(&self, name: String) -> Result<(), Error> {
+        let write_txn = self.0.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            table.remove(name)?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    pub fn clear_expire(&self) -> Result<(), Error> {
+        let write_txn = self.0.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            table.retain(|_, (_, timestamp)| now.saturating_sub(timestamp) < EXPIRE_TIME)?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+use std::{collections::HashMap, sync::Arc};
+
+use redb::{Error, ReadableTable, TableDefinition, TypeName, Value};
+use serde::{Deserialize, Serialize};
+
+use super::Db;
+
+// 键为 bangumi_tv_id:季:集，以 JSON 保存，之后增加的字段需要有默认值
+const TABLE: TableDefinition<String, Release> = TableDefinition::new("release");
+
+#[derive(Debug)]
+pub struct Releases(pub Arc<Db>);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Release {
+    /// 实际下载的版本
+    pub chosen: String,
+    /// 同一集的所有版本，包括被选中的
+    pub candidates: Vec<String>,
+    /// 各个版本的磁力链接，选中的版本下载失败时改为下载其他版本
+    #[serde(default)]
+    pub magnets: HashMap<String, String>,
+}
+
+impl Releases {
+    pub(super) fn init(&self) -> Result<(), Error> {
+        let write_txn = self.0.begin_write()?;
+        write_txn.open_table(TABLE)?;
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    pub fn insert(&self, key: String, release: Release) -> Result<(), Error> {
+        let write_txn = self.0.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            table.insert(key, Some(release))?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    pub fn get(&self, key: String) -> Result<Option<Release>, Error> {
+        let read_txn = self.0.begin_read()?;
+        let table = read_txn.open_table(TABLE)?;
+        let release = table.get(key)?;
+        let release = release.and_then(|s| s.value());
+
+        Ok(release)
+    }
+
+    // 记录新出现的版本并重新选择，在同一个写事务中完成，避免多个订阅源同时选中同一集，
+    // 返回原来和现在选中的版本
+    pub fn merge(
+        &self,
+        key: String,
+        items: Vec<(String, String)>,
+        choose: impl FnOnce(Option<&str>) -> String,
+    ) -> Result<(Option<String>, String), Error> {
+        let write_txn = self.0.begin_write()?;
+        let ret = {
+            let mut table = write_txn.open_table(TABLE)?;
+            let old = table.get(key.clone())?.and_then(|s| s.value());
+            let old_chosen = old.as_ref().map(|r| r.chosen.clone());
+            let mut release = old.unwrap_or_else(|| Release {
+                chosen: String::new(),
+                candidates: Vec::new(),
+                magnets: HashMap::new(),
+            });
+            for (name, magnet) in items {
+                if !release.candidates.contains(&name) {
+                    release.candidates.push(name.clone());
+                }
+                release.magnets.insert(name, magnet);
+            }
+            release.chosen = choose(old_chosen.as_deref());
+
+            let chosen = release.chosen.clone();
+            table.insert(key, Some(release))?;
+            (old_chosen, chosen)
+        };
+        write_txn.commit()?;
+        Ok(ret)
+    }
+}
+
+// 无法解析的记录读取为 None，视为还没有选择版本
+impl Value for Release {
+    type SelfType<'a>
+        = Option<Self>
+    where
+        Self: 'a;
+    type AsBytes<'a>
+        = Vec<u8>
+    where
+        Self: 'a;
+
+    fn fixed_width() -> Option<usize> {
+        None
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'a,
+        Self: 'b,
+    {
+        serde_json::to_vec(value).unwrap()
+    }
+
+    fn type_name() -> redb::TypeName {
+        TypeName::new("release")
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        serde_json::from_slice(data)
+            .map_err(|e| tracing::error!("Error decoding release: {}", e))
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let json = r#"{"chosen":"a","candidates":["a","b"]}"#;
+        let release = Release::from_bytes(json.as_bytes()).unwrap();
+        assert_eq!(release.chosen, "a");
+        assert_eq!(release.candidates, ["a", "b"]);
+
+        assert!(Release::from_bytes(b"not a release").is_none());
+    }
+}
@@ -0,0 +1,145 @@
+use std::sync::Arc;
+
+use redb::{Error, TableDefinition, TypeName, Value};
+use serde::{Deserialize, Serialize};
+
+use super::Db;
+
+// 未完成的分块上传，键为上传目标和远程路径
+const TABLE: TableDefinition<String, Session> = TableDefinition::new("upload_session");
+// OneDrive 的上传会话一般在几天内过期
+const EXPIRE_TIME: u64 = 60 * 60 * 24 * 7;
+
+#[derive(Debug)]
+pub struct Sessions(pub Arc<Db>);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    /// 文件大小，与本次上传不一致时不能续传
+    pub size: u64,
+    /// 已经确认上传的字节数
+    pub offset: u64,
+    /// OneDrive 的上传地址
+    pub url: Option<String>,
+    pub updated_at: u64,
+}
+
+impl Session {
+    pub fn new(size: u64, offset: u64, url: Option<String>) -> Self {
+        Self {
+            size,
+            offset,
+            url,
+            updated_at: now(),
+        }
+    }
+}
+
+impl Sessions {
+    pub fn open(db: Arc<Db>) -> Result<Self, Error> {
+        let write_txn = db.begin_write()?;
+        write_txn.open_table(TABLE)?;
+        write_txn.commit()?;
+        Ok(Self(db))
+    }
+
+    // 定期清理过期的会话
+    pub(super) fn init(&self) {
+        tokio::spawn(async move {
+            // sleep 随机时间，避免同时清理
+            tokio::time::sleep(tokio::time::Duration::from_secs(rand::random::<u64>() % 60)).await;
+            loop {
+                Db::get_session()
+                    .unwrap()
+                    .clear_expire()
+                    .unwrap_or_else(|e| {
+                        tracing::error!("Error clearing expired upload session: {}", e);
+                    });
+
+                tokio::time::sleep(tokio::time::Duration::from_secs(60 * 60 * 24)).await;
+            }
+        });
+    }
+
+    pub fn insert(&self, key: &str, session: Session) -> Result<(), Error> {
+        let write_txn = self.0.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            table.insert(key.to_string(), session)?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<Session>, Error> {
+        let read_txn = self.0.begin_read()?;
+        let table = read_txn.open_table(TABLE)?;
+        let session = table.get(key.to_string())?;
+        let session = session.map(|s| s.value().to_owned());
+
+        Ok(session)
+    }
+
+    pub fn remove(&self, key: &str) -> Result<(), Error> {
+        let write_txn = self.0.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            table.remove(key.to_string())?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    pub fn clear_expire(&self) -> Result<(), Error> {
+        let write_txn = self.0.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            table.retain(|_, session| now() - session.updated_at < EXPIRE_TIME)?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+impl Value for Session {
+    type SelfType<'a>
+        = Self
+    where
+        Self: 'a;
+    type AsBytes<'a>
+        = Vec<u8>
+    where
+        Self: 'a;
+
+    fn fixed_width() -> Option<usize> {
+        None
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'a,
+        Self: 'b,
+    {
+        bincode::serde::encode_to_vec(value, bincode::config::legacy()).unwrap()
+    }
+
+    fn type_name() -> redb::TypeName {
+        TypeName::new("upload_session")
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        bincode::serde::decode_from_slice(data, bincode::config::legacy())
+            .unwrap()
+            .0
+    }
+}
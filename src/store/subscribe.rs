@@ -5,6 +5,8 @@ use redb::{Error, TableDefinition};
 use super::Db;
 
 const TABLE: TableDefinition<String, u64> = TableDefinition::new("subscribe");
+// 记录每个剧集来自哪个订阅源，包括没有被选中下载的版本
+const FEED: TableDefinition<String, String> = TableDefinition::new("subscribe_feed");
 
 const EXPIRE_TIME: u64 = 60 * 60 * 24 * 365;
 
@@ -15,6 +17,7 @@ impl Subscribe {
     pub(super) fn init(&self) -> Result<(), Error> {
         let write_txn = self.0.begin_write()?;
         write_txn.open_table(TABLE)?;
+        write_txn.open_table(FEED)?;
         write_txn.commit()?;
 
         tokio::spawn(async move {
@@ -35,7 +38,7 @@ impl Subscribe {
         Ok(())
     }
 
-    pub fn insert(&self, name: String) -> Result<(), Error> {
+    pub fn insert(&self, name: String, feed: String) -> Result<(), Error> {
         let write_txn = self.0 .0.begin_write()?;
         {
             let mut table = write_txn.open_table(TABLE)?;
@@ -43,7 +46,10 @@ impl Subscribe {
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap()
                 .as_secs();
-            table.insert(name, timestamp)?;
+            table.insert(name.clone(), timestamp)?;
+
+            let mut table = write_txn.open_table(FEED)?;
+            table.insert(name, feed)?;
         }
         write_txn.commit()?;
         Ok(())
@@ -58,19 +64,40 @@ impl Subscribe {
         Ok(timestamp)
     }
 
+    /// 旧版本记录的剧集没有订阅源
+    pub fn get_feed(&self, name: String) -> Result<Option<String>, Error> {
+        let read_txn = self.0.begin_read()?;
+        let table = read_txn.open_table(FEED)?;
+        let feed = table.get(name)?;
+        let feed = feed.map(|s| s.value().to_owned());
+
+        Ok(feed)
+    }
+
     pub fn clear_expire(&self) -> Result<(), Error> {
         let write_txn = self.0.begin_write()?;
         {
             let mut table = write_txn.open_table(TABLE)?;
-            table.retain(|_, value| {
+            let mut expired = Vec::new();
+            table.retain(|name, value| {
                 let timestamp = value;
                 let now = std::time::SystemTime::now()
                     .duration_since(std::time::UNIX_EPOCH)
                     .unwrap()
                     .as_secs();
-                now - timestamp < EXPIRE_TIME
+                let keep = now - timestamp < EXPIRE_TIME;
+                if !keep {
+                    expired.push(name.to_owned());
+                }
+                keep
             })?;
+
+            let mut table = write_txn.open_table(FEED)?;
+            for name in expired {
+                table.remove(name)?;
+            }
         }
+        write_txn.commit()?;
         Ok(())
     }
 }
@@ -0,0 +1,226 @@
+use base64::Engine as _;
+use sha2::Digest as _;
+use snafu::ResultExt;
+use tokio::io::AsyncReadExt as _;
+
+use super::{Error, IoSnafu, Reader};
+
+/// 远程文件的校验值
+#[derive(Debug, Clone, PartialEq)]
+pub enum Checksum {
+    /// OneDrive 使用的 QuickXorHash，base64 编码
+    QuickXor(String),
+    /// 十六进制编码的 SHA-256
+    Sha256(String),
+    /// S3 分块上传的组合校验值，形如 `base64-块数`，为各块 SHA-256 拼接后的 SHA-256
+    Sha256Composite { checksum: String, part_size: u64 },
+}
+
+impl Checksum {
+    /// 计算 reader 的 SHA-256
+    pub async fn sha256(reader: Reader) -> Result<Self, Error> {
+        let mut hasher = sha2::Sha256::new();
+        read_all(reader, |buf| hasher.update(buf)).await?;
+        Ok(Checksum::Sha256(format!("{:x}", hasher.finalize())))
+    }
+
+    /// 按 part_size 分块计算 reader 的组合 SHA-256
+    pub async fn sha256_composite(reader: Reader, part_size: u64) -> Result<Self, Error> {
+        let mut digests = sha2::Sha256::new();
+        let mut part = sha2::Sha256::new();
+        let mut filled = 0;
+        let mut parts = 0;
+        read_all(reader, |mut buf| {
+            while !buf.is_empty() {
+                let n = buf.len().min((part_size - filled) as usize);
+                part.update(&buf[..n]);
+                buf = &buf[n..];
+                filled += n as u64;
+                if filled == part_size {
+                    digests.update(part.finalize_reset());
+                    parts += 1;
+                    filled = 0;
+                }
+            }
+        })
+        .await?;
+        if filled > 0 {
+            digests.update(part.finalize());
+            parts += 1;
+        }
+
+        let checksum = base64::engine::general_purpose::STANDARD.encode(digests.finalize());
+        Ok(Checksum::Sha256Composite {
+            checksum: format!("{}-{}", checksum, parts),
+            part_size,
+        })
+    }
+
+    /// 计算 reader 的 QuickXorHash
+    pub async fn quick_xor(reader: Reader) -> Result<Self, Error> {
+        let mut hasher = QuickXorHash::default();
+        read_all(reader, |buf| hasher.update(buf)).await?;
+        Ok(Checksum::QuickXor(hasher.finish()))
+    }
+
+    /// 用相同的算法计算 reader 的校验值并比较
+    pub async fn verify(&self, reader: Reader) -> Result<bool, Error> {
+        let actual = match self {
+            Checksum::QuickXor(_) => Self::quick_xor(reader).await?,
+            Checksum::Sha256(_) => Self::sha256(reader).await?,
+            Checksum::Sha256Composite { part_size, .. } => {
+                Self::sha256_composite(reader, *part_size).await?
+            }
+        };
+        Ok(match (self, &actual) {
+            (Checksum::Sha256(a), Checksum::Sha256(b)) => a.eq_ignore_ascii_case(b),
+            _ => *self == actual,
+        })
+    }
+}
+
+async fn read_all(mut reader: Reader, mut f: impl FnMut(&[u8])) -> Result<(), Error> {
+    let mut buf = vec![0; 1024 * 1024];
+    loop {
+        let n = reader.read(&mut buf).await.context(IoSnafu)?;
+        if n == 0 {
+            return Ok(());
+        }
+        f(&buf[..n]);
+    }
+}
+
+const WIDTH: usize = 160;
+const SHIFT: usize = 11;
+
+/// QuickXorHash，参考 https://learn.microsoft.com/onedrive/developer/code-snippets/quickxorhash
+#[derive(Debug, Default)]
+pub struct QuickXorHash {
+    data: [u64; 3],
+    shift: usize,
+    length: u64,
+}
+
+impl QuickXorHash {
+    pub fn update(&mut self, bytes: &[u8]) {
+        let mut index = self.shift / 64;
+        let mut offset = self.shift % 64;
+        for i in 0..bytes.len().min(WIDTH) {
+            let last = index == self.data.len() - 1;
+            // 最后一格只有 160 % 64 = 32 位
+            let bits = if last { WIDTH % 64 } else { 64 };
+            let xored = bytes[i..].iter().step_by(WIDTH).fold(0u8, |x, b| x ^ b) as u64;
+            if offset <= bits - 8 {
+                self.data[index] ^= xored << offset;
+            } else {
+                let next = if last { 0 } else { index + 1 };
+                self.data[index] ^= xored << offset;
+                self.data[next] ^= xored >> (bits - offset);
+            }
+
+            offset += SHIFT;
+            if offset >= bits {
+                index = if last { 0 } else { index + 1 };
+                offset -= bits;
+            }
+        }
+
+        self.shift = (self.shift + SHIFT * (bytes.len() % WIDTH)) % WIDTH;
+        self.length += bytes.len() as u64;
+    }
+
+    /// base64 编码的结果，与 Graph API 返回的 quickXorHash 相同
+    pub fn finish(&self) -> String {
+        let mut ret = [0u8; WIDTH / 8];
+        for (i, cell) in self.data.iter().enumerate() {
+            let bytes = cell.to_le_bytes();
+            let start = i * 8;
+            let end = (start + 8).min(ret.len());
+            ret[start..end].copy_from_slice(&bytes[..end - start]);
+        }
+        // 最后 8 个字节与文件长度异或
+        for (i, b) in self.length.to_le_bytes().iter().enumerate() {
+            ret[WIDTH / 8 - 8 + i] ^= b;
+        }
+
+        base64::engine::general_purpose::STANDARD.encode(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quick_xor(data: &[u8]) -> String {
+        let mut hasher = QuickXorHash::default();
+        hasher.update(data);
+        hasher.finish()
+    }
+
+    #[tokio::test]
+    async fn test_quick_xor_hash() {
+        assert_eq!(quick_xor(b""), "AAAAAAAAAAAAAAAAAAAAAAAAAAA=");
+        // 单个字节写入第一位，长度写入第 12 个字节
+        let mut expected = [0u8; 20];
+        expected[0] = b'a';
+        expected[12] = 1;
+        assert_eq!(
+            quick_xor(b"a"),
+            base64::engine::general_purpose::STANDARD.encode(expected)
+        );
+
+        // 分多次写入与一次写入的结果相同
+        let data = (0..100_000u32).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        let mut hasher = QuickXorHash::default();
+        for chunk in data.chunks(333) {
+            hasher.update(chunk);
+        }
+        assert_eq!(hasher.finish(), quick_xor(&data));
+
+        let checksum = Checksum::QuickXor(quick_xor(&data));
+        assert!(checksum
+            .verify(Box::new(std::io::Cursor::new(data.clone())))
+            .await
+            .unwrap());
+        assert!(!checksum
+            .verify(Box::new(std::io::Cursor::new(data[1..].to_vec())))
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_sha256() {
+        let checksum = Checksum::Sha256(
+            "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD".into(),
+        );
+        assert!(checksum
+            .verify(Box::new(std::io::Cursor::new(b"abc")))
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_sha256_composite() {
+        // 最后一块不足 part_size
+        let data = (0..10u8).collect::<Vec<_>>();
+        let mut digests = sha2::Sha256::new();
+        for part in data.chunks(4) {
+            digests.update(sha2::Sha256::digest(part));
+        }
+        let checksum = Checksum::Sha256Composite {
+            checksum: format!(
+                "{}-3",
+                base64::engine::general_purpose::STANDARD.encode(digests.finalize())
+            ),
+            part_size: 4,
+        };
+        assert!(checksum
+            .verify(Box::new(std::io::Cursor::new(data.clone())))
+            .await
+            .unwrap());
+        assert!(!checksum
+            .verify(Box::new(std::io::Cursor::new(data[..8].to_vec())))
+            .await
+            .unwrap());
+    }
+}
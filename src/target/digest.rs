@@ -0,0 +1,202 @@
+use sha2::Digest as _;
+
+/// 服务器在 WWW-Authenticate 中给出的摘要认证质询，参考 RFC 7616
+#[derive(Debug, Clone)]
+pub struct Challenge {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    algorithm: Algorithm,
+    /// 服务器支持 qop=auth 时为 true，否则使用 RFC 2069 的旧格式
+    qop: bool,
+    /// 同一个 nonce 已经使用的次数
+    nc: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Algorithm {
+    Md5,
+    Md5Sess,
+    Sha256,
+    Sha256Sess,
+}
+
+impl Algorithm {
+    fn hash(self, data: &str) -> String {
+        match self {
+            Algorithm::Md5 | Algorithm::Md5Sess => format!("{:x}", md5::Md5::digest(data)),
+            Algorithm::Sha256 | Algorithm::Sha256Sess => {
+                format!("{:x}", sha2::Sha256::digest(data))
+            }
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Algorithm::Md5 => "MD5",
+            Algorithm::Md5Sess => "MD5-sess",
+            Algorithm::Sha256 => "SHA-256",
+            Algorithm::Sha256Sess => "SHA-256-sess",
+        }
+    }
+}
+
+impl Challenge {
+    /// 解析 `Digest realm="...", nonce="...", ...`，不是摘要认证或算法不支持时返回 None
+    pub fn parse(header: &str) -> Option<Self> {
+        let (scheme, params) = header.trim().split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("Digest") {
+            return None;
+        }
+
+        let params = params_of(params);
+        let get = |key: &str| {
+            params
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                .map(|(_, v)| v.clone())
+        };
+        let algorithm = match get("algorithm") {
+            None => Algorithm::Md5,
+            Some(a) if a.eq_ignore_ascii_case("MD5") => Algorithm::Md5,
+            Some(a) if a.eq_ignore_ascii_case("MD5-sess") => Algorithm::Md5Sess,
+            Some(a) if a.eq_ignore_ascii_case("SHA-256") => Algorithm::Sha256,
+            Some(a) if a.eq_ignore_ascii_case("SHA-256-sess") => Algorithm::Sha256Sess,
+            Some(_) => return None,
+        };
+        let qop = get("qop").is_some_and(|qop| {
+            qop.split(',')
+                .any(|q| q.trim().eq_ignore_ascii_case("auth"))
+        });
+
+        Some(Self {
+            realm: get("realm")?,
+            nonce: get("nonce")?,
+            opaque: get("opaque"),
+            algorithm,
+            qop,
+            nc: 0,
+        })
+    }
+
+    /// 计算一次请求的 Authorization，uri 为请求的路径和参数
+    pub fn authorization(
+        &mut self,
+        username: &str,
+        password: &str,
+        method: &str,
+        uri: &str,
+    ) -> String {
+        let cnonce = format!("{:016x}", rand::random::<u64>());
+        self.nc += 1;
+        self.respond(username, password, method, uri, &cnonce)
+    }
+
+    fn respond(
+        &self,
+        username: &str,
+        password: &str,
+        method: &str,
+        uri: &str,
+        cnonce: &str,
+    ) -> String {
+        let hash = |data: String| self.algorithm.hash(&data);
+        let nc = format!("{:08x}", self.nc);
+
+        let mut ha1 = hash(format!("{}:{}:{}", username, self.realm, password));
+        if matches!(self.algorithm, Algorithm::Md5Sess | Algorithm::Sha256Sess) {
+            ha1 = hash(format!("{}:{}:{}", ha1, self.nonce, cnonce));
+        }
+        let ha2 = hash(format!("{}:{}", method, uri));
+        let response = if self.qop {
+            hash(format!(
+                "{}:{}:{}:{}:auth:{}",
+                ha1, self.nonce, nc, cnonce, ha2
+            ))
+        } else {
+            hash(format!("{}:{}:{}", ha1, self.nonce, ha2))
+        };
+
+        let mut header = format!(
+            "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", algorithm={}, response=\"{}\"",
+            username,
+            self.realm,
+            self.nonce,
+            uri,
+            self.algorithm.name(),
+            response
+        );
+        if self.qop {
+            header.push_str(&format!(", qop=auth, nc={}, cnonce=\"{}\"", nc, cnonce));
+        }
+        if let Some(opaque) = &self.opaque {
+            header.push_str(&format!(", opaque=\"{}\"", opaque));
+        }
+        header
+    }
+}
+
+// 逗号分隔的 key=value，引号中的值可以包含逗号
+fn params_of(s: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut rest = s.trim();
+    while let Some((key, value)) = rest.split_once('=') {
+        let key = key.trim().trim_start_matches(',').trim().to_owned();
+        let value = value.trim_start();
+        let (value, next) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                (
+                    quoted[..end].to_owned(),
+                    quoted.get(end + 1..).unwrap_or(""),
+                )
+            }
+            None => {
+                let end = value.find(',').unwrap_or(value.len());
+                (value[..end].trim().to_owned(), &value[end..])
+            }
+        };
+        params.push((key, value));
+        rest = next.trim_start();
+    }
+    params
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 2617 3.5 的示例
+    #[test]
+    fn test_md5() {
+        let mut challenge = Challenge::parse(
+            r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#,
+        )
+        .unwrap();
+        challenge.nc = 1;
+        let header = challenge.respond(
+            "Mufasa",
+            "Circle Of Life",
+            "GET",
+            "/dir/index.html",
+            "0a4f113b",
+        );
+        assert!(header.contains(r#"response="6629fae49393a05397450978507c4ef1""#));
+        assert!(header.contains("qop=auth, nc=00000001"));
+        assert!(header.contains(r#"opaque="5ccc069c403ebaf9f0171e9517f40e41""#));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(Challenge::parse(r#"Basic realm="dav""#).is_none());
+        assert!(
+            Challenge::parse(r#"Digest realm="dav", nonce="n", algorithm=SHA-512-256"#).is_none()
+        );
+
+        let challenge =
+            Challenge::parse(r#"Digest realm="a, b", nonce="n", algorithm=SHA-256"#).unwrap();
+        assert_eq!(challenge.realm, "a, b");
+        assert_eq!(challenge.algorithm, Algorithm::Sha256);
+        assert!(!challenge.qop);
+    }
+}
@@ -0,0 +1,310 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use snafu::ResultExt;
+use tokio::io::{AsyncSeekExt as _, AsyncWriteExt as _};
+
+use crate::{
+    store::{UploadSession, UploadSessions},
+    util::config::LocalMode,
+};
+
+use super::{
+    load_session, part_path, read_chunk, remove_session, save_session, session_key, skip, Checksum,
+    Error, IoSnafu, Reader, Remote, Target, CHUNK_SIZE,
+};
+
+pub struct Local {
+    name: String,
+    root: PathBuf,
+    mode: LocalMode,
+    sessions: Arc<UploadSessions>,
+    chunk_size: u64,
+}
+
+impl Local {
+    pub fn new(name: &str, root: PathBuf, mode: LocalMode, sessions: Arc<UploadSessions>) -> Self {
+        Self {
+            name: name.to_owned(),
+            root,
+            mode,
+            sessions,
+            chunk_size: CHUNK_SIZE,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Target for Local {
+    // 先写入临时文件，每写完一块记录位置，重启后从临时文件末尾继续
+    async fn upload(&self, mut reader: Reader, size: u64, path: &Path) -> Result<(), Error> {
+        let dest = self.root.join(path);
+        if let Some(parent) = dest.parent() {
+            tokio::fs::create_dir_all(parent).await.context(IoSnafu)?;
+        }
+        let part = part_path(&dest);
+        let key = session_key(&self.name, path);
+
+        // 记录的位置之后可能还有没来得及记录的数据，以两者中较小的为准
+        let written = tokio::fs::metadata(&part)
+            .await
+            .map(|m| m.len())
+            .unwrap_or(0);
+        let mut offset = load_session(&self.sessions, &key, size)?
+            .map(|s| s.offset.min(written))
+            .unwrap_or(0);
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(&part)
+            .await
+            .context(IoSnafu)?;
+        file.set_len(offset).await.context(IoSnafu)?;
+        file.seek(std::io::SeekFrom::Start(offset))
+            .await
+            .context(IoSnafu)?;
+        skip(&mut reader, offset).await?;
+
+        while offset < size {
+            let len = self.chunk_size.min(size - offset);
+            let chunk = read_chunk(&mut reader, len).await?;
+            file.write_all(&chunk).await.context(IoSnafu)?;
+            file.sync_data().await.context(IoSnafu)?;
+
+            offset += len;
+            save_session(&self.sessions, &key, UploadSession::new(size, offset, None))?;
+        }
+        drop(file);
+
+        tokio::fs::rename(&part, &dest).await.context(IoSnafu)?;
+        remove_session(&self.sessions, &key)
+    }
+
+    async fn delete(&self, path: &Path) -> Result<(), Error> {
+        match tokio::fs::remove_file(self.root.join(path)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e).context(IoSnafu),
+            _ => Ok(()),
+        }
+    }
+
+    async fn link(&self, source: &Path, path: &Path) -> Result<bool, Error> {
+        if self.mode == LocalMode::Copy {
+            return Ok(false);
+        }
+
+        let dest = self.root.join(path);
+        if let Some(parent) = dest.parent() {
+            tokio::fs::create_dir_all(parent).await.context(IoSnafu)?;
+        }
+        // 之前移动过的文件在下载目录中只剩符号链接，需要找到真实的文件
+        let real = tokio::fs::canonicalize(source).await.context(IoSnafu)?;
+        if tokio::fs::canonicalize(&dest).await.ok().as_ref() == Some(&real) {
+            return Ok(true);
+        }
+        if self.mode == LocalMode::Move && real != source {
+            return Ok(false);
+        }
+        match tokio::fs::remove_file(&dest).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e).context(IoSnafu),
+            _ => {}
+        }
+
+        let ret = match self.mode {
+            LocalMode::Hardlink => tokio::fs::hard_link(&real, &dest).await,
+            LocalMode::Reflink => reflink(real, dest.clone()).await,
+            LocalMode::Move => move_file(&real, &dest).await,
+            LocalMode::Copy => unreachable!(),
+        };
+        match ret {
+            Ok(()) => Ok(true),
+            // 跨文件系统等情况退回复制
+            Err(e) => {
+                tracing::warn!(
+                    "Error placing {} with {:?}, falling back to copy: {}",
+                    dest.display(),
+                    self.mode,
+                    e
+                );
+                Ok(false)
+            }
+        }
+    }
+
+    async fn stat(&self, path: &Path) -> Result<Option<Remote>, Error> {
+        let file = match tokio::fs::File::open(self.root.join(path)).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context(IoSnafu),
+        };
+        let size = file.metadata().await.context(IoSnafu)?.len();
+        let checksum = Checksum::sha256(Box::new(tokio::io::BufReader::new(file))).await?;
+
+        Ok(Some(Remote {
+            size,
+            checksum: Some(checksum),
+        }))
+    }
+}
+
+// 移动到媒体库后在原位置创建符号链接，做种时仍然可以读取
+async fn move_file(source: &Path, dest: &Path) -> std::io::Result<()> {
+    tokio::fs::rename(source, dest).await?;
+    let ret = match tokio::fs::canonicalize(dest).await {
+        Ok(dest) => tokio::fs::symlink(dest, source).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = ret {
+        tokio::fs::rename(dest, source).await?;
+        return Err(e);
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+async fn reflink(source: PathBuf, dest: PathBuf) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd as _;
+
+    tokio::task::spawn_blocking(move || {
+        let src = std::fs::File::open(&source)?;
+        let dst = std::fs::File::create_new(&dest)?;
+        // SAFETY: 两个文件描述符在调用期间都有效
+        let ret = unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) };
+        if ret == -1 {
+            let e = std::io::Error::last_os_error();
+            drop(dst);
+            let _ = std::fs::remove_file(&dest);
+            return Err(e);
+        }
+        Ok(())
+    })
+    .await?
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn reflink(_source: PathBuf, _dest: PathBuf) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::target::temp_sessions;
+
+    #[tokio::test]
+    async fn test_resume() {
+        let root = std::env::temp_dir().join("mikan-subscriber-test-local-resume");
+        let _ = tokio::fs::remove_dir_all(&root).await;
+        tokio::fs::create_dir_all(root.join("dir")).await.unwrap();
+
+        let data = (0..10u8).collect::<Vec<_>>();
+        let path = Path::new("dir/file.mkv");
+        let (_dir, sessions) = temp_sessions();
+        let local = Local {
+            name: "test-local-resume".into(),
+            root: root.clone(),
+            mode: LocalMode::Copy,
+            sessions,
+            chunk_size: 4,
+        };
+
+        // 上一次上传确认了 4 个字节，临时文件中多写了 2 个字节
+        tokio::fs::write(root.join("dir/file.mkv.part"), [0, 1, 2, 3, 0xff, 0xff])
+            .await
+            .unwrap();
+        save_session(
+            &local.sessions,
+            &session_key(&local.name, path),
+            UploadSession::new(data.len() as u64, 4, None),
+        )
+        .unwrap();
+
+        local
+            .upload(
+                Box::new(std::io::Cursor::new(data.clone())),
+                data.len() as u64,
+                path,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(tokio::fs::read(root.join(path)).await.unwrap(), data);
+        assert!(!root.join("dir/file.mkv.part").exists());
+        let remote = local.stat(path).await.unwrap().unwrap();
+        assert_eq!(remote.size, data.len() as u64);
+        assert!(remote
+            .checksum
+            .unwrap()
+            .verify(Box::new(std::io::Cursor::new(data.clone())))
+            .await
+            .unwrap());
+        assert!(load_session(
+            &local.sessions,
+            &session_key(&local.name, path),
+            data.len() as u64
+        )
+        .unwrap()
+        .is_none());
+
+        tokio::fs::remove_dir_all(&root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_link() {
+        use std::os::unix::fs::MetadataExt as _;
+
+        let dir = std::env::temp_dir().join("mikan-subscriber-test-local-link");
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        tokio::fs::create_dir_all(dir.join("download"))
+            .await
+            .unwrap();
+        let source = dir.join("download/file.mkv");
+        tokio::fs::write(&source, b"data").await.unwrap();
+        let (_dir, sessions) = temp_sessions();
+
+        let hardlink = Local::new(
+            "test-local-hardlink",
+            dir.join("hardlink"),
+            LocalMode::Hardlink,
+            sessions.clone(),
+        );
+        assert!(hardlink
+            .link(&source, Path::new("a/file.mkv"))
+            .await
+            .unwrap());
+        let linked = dir.join("hardlink/a/file.mkv");
+        assert_eq!(
+            std::fs::metadata(&linked).unwrap().ino(),
+            std::fs::metadata(&source).unwrap().ino()
+        );
+
+        let moved = Local::new(
+            "test-local-move",
+            dir.join("move"),
+            LocalMode::Move,
+            sessions.clone(),
+        );
+        assert!(moved.link(&source, Path::new("a/file.mkv")).await.unwrap());
+        let dest = dir.join("move/a/file.mkv");
+        assert!(!std::fs::symlink_metadata(&dest).unwrap().is_symlink());
+        assert!(std::fs::symlink_metadata(&source).unwrap().is_symlink());
+        assert_eq!(tokio::fs::read(&source).await.unwrap(), b"data");
+        // 再次放置时文件已经在目标位置
+        assert!(moved.link(&source, Path::new("a/file.mkv")).await.unwrap());
+        assert_eq!(tokio::fs::read(&dest).await.unwrap(), b"data");
+
+        // 下载目录中只剩符号链接时不能再移动
+        let other = Local::new(
+            "test-local-move-other",
+            dir.join("other"),
+            LocalMode::Move,
+            sessions,
+        );
+        assert!(!other.link(&source, Path::new("a/file.mkv")).await.unwrap());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
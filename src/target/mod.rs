@@ -0,0 +1,168 @@
+mod checksum;
+mod digest;
+mod local;
+mod onedrive;
+mod rclone;
+mod s3;
+mod sftp;
+mod throttle;
+mod webdav;
+
+use std::path::{Path, PathBuf};
+
+use snafu::{ResultExt, Snafu};
+use tokio::io::{AsyncRead, AsyncReadExt as _};
+
+use crate::store::{UploadSession, UploadSessions};
+
+pub use checksum::Checksum;
+pub use local::Local;
+pub use onedrive::Onedrive;
+pub use rclone::Rclone;
+pub use s3::S3;
+pub use sftp::Sftp;
+pub use throttle::Throttle;
+pub use webdav::Webdav;
+
+pub type Reader = Box<dyn AsyncRead + Unpin + Send + Sync>;
+
+/// 上传目标，在 upload_backend 的基础上补充了删除等远程文件操作
+#[async_trait::async_trait]
+pub trait Target: Send + Sync {
+    async fn upload(&self, reader: Reader, size: u64, path: &Path) -> Result<(), Error>;
+
+    /// 删除远程文件，文件不存在时视为成功
+    async fn delete(&self, path: &Path) -> Result<(), Error>;
+
+    /// 远程文件的大小和校验值，文件不存在时返回 None
+    async fn stat(&self, path: &Path) -> Result<Option<Remote>, Error>;
+
+    /// 不经过上传直接放置本地文件，不支持或无法完成时返回 false，由调用方改为上传
+    async fn link(&self, _source: &Path, _path: &Path) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    /// 授权失效需要用户重新登录，期间暂停上传且不计入重试次数
+    async fn needs_reauth(&self) -> bool {
+        false
+    }
+}
+
+/// 上传后用于校验的远程文件信息
+#[derive(Debug)]
+pub struct Remote {
+    pub size: u64,
+    /// 目标不提供校验值时为 None，只比较大小
+    pub checksum: Option<Checksum>,
+}
+
+// 分块上传的大小，OneDrive 要求为 320 KiB 的整数倍
+const CHUNK_SIZE: u64 = 10 * 1024 * 1024;
+
+// 同一目标同一路径的上传共用一个会话
+fn session_key(target: &str, path: &Path) -> String {
+    format!("{}:{}", target, path.display())
+}
+
+// 读取文件大小相同的未完成上传
+fn load_session(
+    sessions: &UploadSessions,
+    key: &str,
+    size: u64,
+) -> Result<Option<UploadSession>, Error> {
+    let session = sessions.get(key).context(DbSnafu)?;
+    Ok(session.filter(|s| s.size == size))
+}
+
+fn save_session(sessions: &UploadSessions, key: &str, session: UploadSession) -> Result<(), Error> {
+    sessions.insert(key, session).context(DbSnafu)
+}
+
+fn remove_session(sessions: &UploadSessions, key: &str) -> Result<(), Error> {
+    sessions.remove(key).context(DbSnafu)
+}
+
+// 测试使用临时数据库，避免写入 store.db
+#[cfg(test)]
+fn temp_sessions() -> (tempfile::TempDir, std::sync::Arc<UploadSessions>) {
+    let dir = tempfile::tempdir().unwrap();
+    let db = crate::store::Db::open(dir.path().join("store.db")).unwrap();
+    (dir, std::sync::Arc::new(UploadSessions::open(db).unwrap()))
+}
+
+// 上传过程中使用的临时文件名，完成后再重命名
+fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    path.with_file_name(name)
+}
+
+// 续传时跳过已经上传的部分
+async fn skip(reader: &mut Reader, bytes: u64) -> Result<(), Error> {
+    let skipped = tokio::io::copy(&mut reader.take(bytes), &mut tokio::io::sink())
+        .await
+        .context(IoSnafu)?;
+    if skipped != bytes {
+        return Err(Error::Upload {
+            error: format!("file is shorter than uploaded offset {}", bytes),
+        });
+    }
+    Ok(())
+}
+
+async fn read_chunk(reader: &mut Reader, len: u64) -> Result<Vec<u8>, Error> {
+    let mut buf = vec![0; len as usize];
+    reader.read_exact(&mut buf).await.context(IoSnafu)?;
+    Ok(buf)
+}
+
+// 将相对路径拆分为各级名称
+fn components(path: &Path) -> Vec<String> {
+    path.components()
+        .filter_map(|c| match c {
+            std::path::Component::Normal(s) => Some(s.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
+pub enum Error {
+    #[snafu(display("Upload error: {}", error))]
+    Upload { error: String },
+
+    #[snafu(display("Request error: {}", source))]
+    Request { source: reqwest::Error },
+
+    #[snafu(display("Unexpected status {} from {}", status, url))]
+    Status {
+        status: reqwest::StatusCode,
+        url: String,
+    },
+
+    #[snafu(display("Invalid url {}", url))]
+    Url { url: String },
+
+    #[snafu(display("Error IO: {}", source))]
+    Io { source: std::io::Error },
+
+    #[snafu(display("Error loading DB: {}", source))]
+    Db { source: redb::Error },
+
+    #[snafu(display("SSH error: {}", source))]
+    Ssh { source: ssh2::Error },
+
+    #[snafu(display(
+        "Untrusted host key {} for {}, set it as host_key if expected",
+        fingerprint,
+        host
+    ))]
+    HostKey { host: String, fingerprint: String },
+
+    #[snafu(display("rclone exited with {}: {}", code, stderr))]
+    Rclone { code: i32, stderr: String },
+
+    #[snafu(display("{} needs to be authorized again", name))]
+    Reauth { name: String },
+}
@@ -0,0 +1,713 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use snafu::ResultExt;
+use tokio::sync::Mutex;
+use upload_backend::backend::OnedriveApiType;
+use url::Url;
+
+use crate::{
+    store::{OnedriveTokens, UploadSession, UploadSessions},
+    util::reqwest::client,
+};
+
+use super::{
+    components, load_session, read_chunk, remove_session, save_session, session_key, skip,
+    Checksum, DbSnafu, Error, Reader, Remote, RequestSnafu, Target, CHUNK_SIZE,
+};
+
+const GRAPH_URL: &str = "https://graph.microsoft.com/v1.0/me/drive/";
+const LOGIN_URL: &str = "https://login.microsoftonline.com/";
+const SCOPE: &str = "offline_access Files.ReadWrite.All";
+const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+pub struct Onedrive {
+    name: String,
+    client_id: String,
+    client_secret: String,
+    api_type: OnedriveApiType,
+    root: PathBuf,
+    token: Mutex<Option<(String, Instant)>>,
+    graph_url: String,
+    login_url: String,
+    tokens: Arc<OnedriveTokens>,
+    sessions: Arc<UploadSessions>,
+    chunk_size: u64,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UploadSessionResponse {
+    upload_url: Option<String>,
+    #[serde(default)]
+    next_expected_ranges: Vec<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct DriveItem {
+    size: u64,
+    file: Option<FileFacet>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct FileFacet {
+    hashes: Option<Hashes>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Hashes {
+    quick_xor_hash: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_in: u64,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct OauthError {
+    error: String,
+    #[serde(default)]
+    error_description: String,
+}
+
+/// 设备码登录时需要展示给用户的信息
+#[derive(Debug, serde::Deserialize)]
+pub struct DeviceCode {
+    device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    expires_in: u64,
+    #[serde(default = "default_interval")]
+    interval: u64,
+}
+
+fn default_interval() -> u64 {
+    5
+}
+
+impl Onedrive {
+    pub fn new(
+        name: &str,
+        client_id: &str,
+        client_secret: &str,
+        api_type: OnedriveApiType,
+        root: PathBuf,
+        tokens: Arc<OnedriveTokens>,
+        sessions: Arc<UploadSessions>,
+    ) -> Self {
+        Self {
+            name: name.to_owned(),
+            client_id: client_id.to_owned(),
+            client_secret: client_secret.to_owned(),
+            api_type,
+            root,
+            token: Mutex::new(None),
+            graph_url: GRAPH_URL.to_owned(),
+            login_url: LOGIN_URL.to_owned(),
+            tokens,
+            sessions,
+            chunk_size: CHUNK_SIZE,
+        }
+    }
+
+    fn oauth_url(&self, endpoint: &str) -> String {
+        let tenant = match self.api_type {
+            OnedriveApiType::Organizations => "organizations",
+            _ => "common",
+        };
+        format!("{}{}/oauth2/v2.0/{}", self.login_url, tenant, endpoint)
+    }
+
+    // 请求 token 接口，OAuth 错误单独返回，由调用方决定如何处理
+    async fn request_token(
+        &self,
+        form: &[(&str, &str)],
+    ) -> Result<Result<TokenResponse, OauthError>, Error> {
+        let url = self.oauth_url("token");
+        let mut form = form.to_vec();
+        form.push(("client_id", &self.client_id));
+        // 设备码登录得到的是公共客户端的 token，刷新时不能携带 client_secret
+        if !self.client_secret.is_empty() {
+            form.push(("client_secret", &self.client_secret));
+        }
+
+        let res = client()
+            .post(&url)
+            .form(&form)
+            .send()
+            .await
+            .context(RequestSnafu)?;
+        if res.status() == reqwest::StatusCode::BAD_REQUEST {
+            let error = res.json::<OauthError>().await.context(RequestSnafu)?;
+            return Ok(Err(error));
+        }
+        if !res.status().is_success() {
+            return Err(Error::Status {
+                status: res.status(),
+                url,
+            });
+        }
+        Ok(Ok(res
+            .json::<TokenResponse>()
+            .await
+            .context(RequestSnafu)?))
+    }
+
+    // 保存新的 refresh token 并缓存 access token
+    fn save_token(
+        &self,
+        res: TokenResponse,
+        token: &mut Option<(String, Instant)>,
+    ) -> Result<String, Error> {
+        if let Some(refresh_token) = res.refresh_token {
+            self.tokens
+                .insert_refresh_token(refresh_token, self.name.clone())
+                .context(DbSnafu)?;
+        }
+        // 提前一分钟过期，避免请求过程中失效
+        let expires_at = Instant::now() + Duration::from_secs(res.expires_in.saturating_sub(60));
+        *token = Some((res.access_token.clone(), expires_at));
+        Ok(res.access_token)
+    }
+
+    /// 开始设备码登录，用户需要在其他设备上打开地址并输入代码
+    pub async fn request_device_code(&self) -> Result<DeviceCode, Error> {
+        let url = self.oauth_url("devicecode");
+        let res = client()
+            .post(&url)
+            .form(&[("client_id", self.client_id.as_str()), ("scope", SCOPE)])
+            .send()
+            .await
+            .context(RequestSnafu)?;
+        if !res.status().is_success() {
+            return Err(Error::Status {
+                status: res.status(),
+                url,
+            });
+        }
+        res.json::<DeviceCode>().await.context(RequestSnafu)
+    }
+
+    /// 轮询直到用户完成授权，refresh token 保存到数据库
+    pub async fn poll_device_code(&self, code: &DeviceCode) -> Result<(), Error> {
+        let deadline = Instant::now() + Duration::from_secs(code.expires_in);
+        let mut interval = code.interval;
+        while Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+            let res = self
+                .request_token(&[
+                    ("grant_type", DEVICE_CODE_GRANT),
+                    ("device_code", &code.device_code),
+                ])
+                .await?;
+            match res {
+                Ok(res) => {
+                    let mut token = self.token.lock().await;
+                    self.save_token(res, &mut token)?;
+                    return Ok(());
+                }
+                Err(e) if e.error == "authorization_pending" => {}
+                Err(e) if e.error == "slow_down" => interval += 5,
+                Err(e) => {
+                    return Err(Error::Upload {
+                        error: format!("Login failed: {} {}", e.error, e.error_description),
+                    })
+                }
+            }
+        }
+        Err(Error::Upload {
+            error: "Device code expired".to_owned(),
+        })
+    }
+
+    // 使用数据库中的 refresh token 获取 access token
+    async fn access_token(&self) -> Result<String, Error> {
+        let mut token = self.token.lock().await;
+        if let Some((access_token, expires_at)) = token.as_ref() {
+            if *expires_at > Instant::now() {
+                return Ok(access_token.clone());
+            }
+        }
+
+        let refresh_token = self
+            .tokens
+            .get_refresh_token(self.name.clone())
+            .context(DbSnafu)?
+            .ok_or_else(|| Error::Reauth {
+                name: self.name.clone(),
+            })?;
+
+        let res = self
+            .request_token(&[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", &refresh_token),
+            ])
+            .await?;
+        match res {
+            Ok(res) => self.save_token(res, &mut token),
+            // refresh token 已经失效或被撤销，删除后等待重新登录
+            Err(e) if e.error == "invalid_grant" => {
+                tracing::warn!(
+                    "Refresh token of {} rejected: {}",
+                    self.name,
+                    e.error_description
+                );
+                self.tokens
+                    .remove_refresh_token(self.name.clone())
+                    .context(DbSnafu)?;
+                Err(Error::Reauth {
+                    name: self.name.clone(),
+                })
+            }
+            Err(e) => Err(Error::Upload {
+                error: format!("Refresh token failed: {} {}", e.error, e.error_description),
+            }),
+        }
+    }
+
+    // 形如 /me/drive/root:/path/to/file: 的地址
+    fn item_url(&self, path: &Path) -> Url {
+        let mut segments = components(&self.root.join(path));
+        if let Some(last) = segments.last_mut() {
+            last.push(':');
+        }
+
+        let mut url = Url::parse(&self.graph_url).unwrap();
+        url.path_segments_mut()
+            .unwrap()
+            .pop_if_empty()
+            .push("root:")
+            .extend(segments);
+        url
+    }
+
+    // 形如 /me/drive/root:/path/to/file:/content 的地址
+    fn item_action_url(&self, path: &Path, action: &str) -> Url {
+        let mut url = self.item_url(path);
+        url.path_segments_mut().unwrap().push(action);
+        url
+    }
+
+    async fn create_session(&self, path: &Path) -> Result<String, Error> {
+        let url = self.item_action_url(path, "createUploadSession");
+        let res = client()
+            .post(url.clone())
+            .bearer_auth(self.access_token().await?)
+            .json(&serde_json::json!({
+                "item": { "@microsoft.graph.conflictBehavior": "replace" }
+            }))
+            .send()
+            .await
+            .context(RequestSnafu)?;
+        if !res.status().is_success() {
+            return Err(Error::Status {
+                status: res.status(),
+                url: url.to_string(),
+            });
+        }
+
+        let res = res
+            .json::<UploadSessionResponse>()
+            .await
+            .context(RequestSnafu)?;
+        res.upload_url.ok_or_else(|| Error::Upload {
+            error: "No uploadUrl in upload session".to_owned(),
+        })
+    }
+
+    // 服务器期望的下一个字节，会话已经过期时返回 None
+    async fn session_offset(&self, upload_url: &str) -> Result<Option<u64>, Error> {
+        let res = client()
+            .get(upload_url)
+            .send()
+            .await
+            .context(RequestSnafu)?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !res.status().is_success() {
+            return Err(Error::Status {
+                status: res.status(),
+                url: upload_url.to_owned(),
+            });
+        }
+
+        let res = res
+            .json::<UploadSessionResponse>()
+            .await
+            .context(RequestSnafu)?;
+        Ok(res
+            .next_expected_ranges
+            .first()
+            .and_then(|r| r.split('-').next())
+            .and_then(|r| r.parse().ok()))
+    }
+
+    // 小文件直接上传
+    async fn upload_small(&self, mut reader: Reader, size: u64, path: &Path) -> Result<(), Error> {
+        let data = read_chunk(&mut reader, size).await?;
+        let url = self.item_action_url(path, "content");
+        let res = client()
+            .put(url.clone())
+            .bearer_auth(self.access_token().await?)
+            .body(data)
+            .send()
+            .await
+            .context(RequestSnafu)?;
+        if !res.status().is_success() {
+            return Err(Error::Status {
+                status: res.status(),
+                url: url.to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Target for Onedrive {
+    // 没有 refresh token 时需要通过 onedrive-login 命令重新登录
+    async fn needs_reauth(&self) -> bool {
+        self.tokens
+            .get_refresh_token(self.name.clone())
+            .is_ok_and(|token| token.is_none())
+    }
+
+    // 使用上传会话分块上传，会话地址保存在数据库中，重启后向服务器查询进度继续上传
+    async fn upload(&self, mut reader: Reader, size: u64, path: &Path) -> Result<(), Error> {
+        if size <= self.chunk_size {
+            return self.upload_small(reader, size, path).await;
+        }
+
+        let key = session_key(&self.name, path);
+        let mut resumed = None;
+        if let Some(UploadSession { url: Some(url), .. }) =
+            load_session(&self.sessions, &key, size)?
+        {
+            if let Some(offset) = self.session_offset(&url).await? {
+                tracing::info!("Resuming {} from {}", path.display(), offset);
+                resumed = Some((url, offset));
+            }
+        }
+        let (upload_url, mut offset) = match resumed {
+            Some(resumed) => resumed,
+            None => (self.create_session(path).await?, 0),
+        };
+        save_session(
+            &self.sessions,
+            &key,
+            UploadSession::new(size, offset, Some(upload_url.clone())),
+        )?;
+        skip(&mut reader, offset).await?;
+
+        while offset < size {
+            let len = self.chunk_size.min(size - offset);
+            let chunk = read_chunk(&mut reader, len).await?;
+            // 上传地址自带鉴权，不能携带 Authorization
+            let res = client()
+                .put(&upload_url)
+                .header(
+                    "Content-Range",
+                    format!("bytes {}-{}/{}", offset, offset + len - 1, size),
+                )
+                .body(chunk)
+                .send()
+                .await
+                .context(RequestSnafu)?;
+
+            match res.status() {
+                reqwest::StatusCode::ACCEPTED => {
+                    offset += len;
+                    save_session(
+                        &self.sessions,
+                        &key,
+                        UploadSession::new(size, offset, Some(upload_url.clone())),
+                    )?;
+                }
+                reqwest::StatusCode::OK | reqwest::StatusCode::CREATED => break,
+                status => {
+                    // 会话已经失效，下次重新创建
+                    if status == reqwest::StatusCode::NOT_FOUND {
+                        remove_session(&self.sessions, &key)?;
+                    }
+                    return Err(Error::Status {
+                        status,
+                        url: upload_url,
+                    });
+                }
+            }
+        }
+
+        remove_session(&self.sessions, &key)
+    }
+
+    async fn delete(&self, path: &Path) -> Result<(), Error> {
+        let url = self.item_url(path);
+        let res = client()
+            .delete(url.clone())
+            .bearer_auth(self.access_token().await?)
+            .send()
+            .await
+            .context(RequestSnafu)?;
+
+        if res.status().is_success() || res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+        Err(Error::Status {
+            status: res.status(),
+            url: url.to_string(),
+        })
+    }
+
+    // 个人版和商业版都提供 quickXorHash
+    async fn stat(&self, path: &Path) -> Result<Option<Remote>, Error> {
+        let url = self.item_url(path);
+        let res = client()
+            .get(url.clone())
+            .bearer_auth(self.access_token().await?)
+            .send()
+            .await
+            .context(RequestSnafu)?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !res.status().is_success() {
+            return Err(Error::Status {
+                status: res.status(),
+                url: url.to_string(),
+            });
+        }
+
+        let item = res.json::<DriveItem>().await.context(RequestSnafu)?;
+        Ok(Some(Remote {
+            size: item.size,
+            checksum: item
+                .file
+                .and_then(|f| f.hashes)
+                .and_then(|h| h.quick_xor_hash)
+                .map(Checksum::QuickXor),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        matchers::{header, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+    use crate::target::temp_sessions;
+
+    fn onedrive(name: &str, server: &MockServer) -> (Onedrive, tempfile::TempDir) {
+        static INIT: std::sync::Once = std::sync::Once::new();
+        INIT.call_once(|| {
+            crate::util::reqwest::init_client(None).unwrap();
+        });
+        let (dir, sessions) = temp_sessions();
+        let tokens = Arc::new(OnedriveTokens::open(sessions.0.clone()).unwrap());
+        let onedrive = Onedrive {
+            name: name.to_owned(),
+            client_id: String::new(),
+            client_secret: String::new(),
+            api_type: OnedriveApiType::Common,
+            root: PathBuf::from("/"),
+            token: Mutex::new(Some((
+                "token".to_owned(),
+                Instant::now() + Duration::from_secs(3600),
+            ))),
+            graph_url: format!("{}/me/drive/", server.uri()),
+            login_url: format!("{}/", server.uri()),
+            tokens,
+            sessions,
+            chunk_size: 4,
+        };
+        (onedrive, dir)
+    }
+
+    fn chunk(session: &str, range: &str, status: u16) -> Mock {
+        Mock::given(method("PUT"))
+            .and(path(format!("/upload/{}", session)))
+            .and(header("Content-Range", range))
+            .respond_with(ResponseTemplate::new(status))
+            .expect(1)
+    }
+
+    #[tokio::test]
+    async fn test_upload_chunks() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/me/drive/root:/dir/file.mkv:/createUploadSession"))
+            .and(header("Authorization", "Bearer token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "uploadUrl": format!("{}/upload/new", server.uri()),
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        chunk("new", "bytes 0-3/10", 202).mount(&server).await;
+        chunk("new", "bytes 4-7/10", 202).mount(&server).await;
+        chunk("new", "bytes 8-9/10", 201).mount(&server).await;
+
+        let data = (0..10u8).collect::<Vec<_>>();
+        let (onedrive, _dir) = onedrive("test-onedrive-chunks", &server);
+        onedrive
+            .upload(
+                Box::new(std::io::Cursor::new(data)),
+                10,
+                Path::new("dir/file.mkv"),
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resume() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/upload/saved"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "nextExpectedRanges": ["4-"],
+            })))
+            .mount(&server)
+            .await;
+        chunk("saved", "bytes 4-7/10", 202).mount(&server).await;
+        chunk("saved", "bytes 8-9/10", 201).mount(&server).await;
+
+        let (onedrive, _dir) = onedrive("test-onedrive-resume", &server);
+        let key = session_key(&onedrive.name, Path::new("dir/file.mkv"));
+        let url = format!("{}/upload/saved", server.uri());
+        save_session(
+            &onedrive.sessions,
+            &key,
+            UploadSession::new(10, 4, Some(url)),
+        )
+        .unwrap();
+
+        let data = (0..10u8).collect::<Vec<_>>();
+        onedrive
+            .upload(
+                Box::new(std::io::Cursor::new(data)),
+                10,
+                Path::new("dir/file.mkv"),
+            )
+            .await
+            .unwrap();
+        assert!(load_session(&onedrive.sessions, &key, 10)
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_stat() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/me/drive/root:/dir/file.mkv:"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "size": 10,
+                "file": { "hashes": { "quickXorHash": "AAAAAAAAAAAAAAAAAAAAAAAAAAA=" } },
+            })))
+            .mount(&server)
+            .await;
+
+        let (onedrive, _dir) = onedrive("test-onedrive-stat", &server);
+        let remote = onedrive
+            .stat(Path::new("dir/file.mkv"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(remote.size, 10);
+        assert_eq!(
+            remote.checksum,
+            Some(Checksum::QuickXor("AAAAAAAAAAAAAAAAAAAAAAAAAAA=".into()))
+        );
+        assert!(onedrive
+            .stat(Path::new("dir/missing.mkv"))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_device_code() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/common/oauth2/v2.0/devicecode"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "device_code": "device",
+                "user_code": "ABCD",
+                "verification_uri": "https://microsoft.com/devicelogin",
+                "expires_in": 60,
+                "interval": 0,
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/common/oauth2/v2.0/token"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "error": "authorization_pending",
+            })))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/common/oauth2/v2.0/token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "access_token": "access",
+                "refresh_token": "refresh",
+                "expires_in": 3600,
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let (onedrive, _dir) = onedrive("test-onedrive-device-code", &server);
+        *onedrive.token.lock().await = None;
+        let code = onedrive.request_device_code().await.unwrap();
+        assert_eq!(code.user_code, "ABCD");
+        onedrive.poll_device_code(&code).await.unwrap();
+
+        assert!(!onedrive.needs_reauth().await);
+        assert_eq!(onedrive.access_token().await.unwrap(), "access");
+    }
+
+    #[tokio::test]
+    async fn test_reauth() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/common/oauth2/v2.0/token"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "error": "invalid_grant",
+                "error_description": "token revoked",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let (onedrive, _dir) = onedrive("test-onedrive-reauth", &server);
+        *onedrive.token.lock().await = None;
+        onedrive
+            .tokens
+            .insert_refresh_token("revoked".into(), onedrive.name.clone())
+            .unwrap();
+        assert!(!onedrive.needs_reauth().await);
+
+        let err = onedrive.access_token().await.unwrap_err();
+        assert!(matches!(err, Error::Reauth { .. }));
+        assert!(onedrive.needs_reauth().await);
+    }
+}
@@ -0,0 +1,255 @@
+use std::{path::Path, process::Stdio};
+
+use base64::Engine as _;
+use snafu::ResultExt;
+use tokio::process::Command;
+
+use crate::util::config;
+
+use super::{components, Checksum, Error, IoSnafu, Reader, Remote, Target};
+
+// rclone 的退出码，3 为目录不存在，4 为文件不存在
+const DIR_NOT_FOUND: i32 = 3;
+const FILE_NOT_FOUND: i32 = 4;
+
+pub struct Rclone {
+    config: config::Rclone,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Entry {
+    size: u64,
+    #[serde(default)]
+    hashes: std::collections::HashMap<String, String>,
+}
+
+impl Rclone {
+    // 加载时运行一次 rclone，没有安装时尽早报错，而不是在上传时才失败
+    pub async fn new(config: config::Rclone) -> Result<Self, Error> {
+        let rclone = Self { config };
+        rclone.run(&["version"]).await?;
+        Ok(rclone)
+    }
+
+    // 远程路径，remote 可以是 `gdrive:` 或 `gdrive:anime`
+    fn remote(&self, path: &Path) -> String {
+        let remote = &self.config.remote;
+        let path = components(path).join("/");
+        if remote.ends_with(':') || remote.ends_with('/') {
+            format!("{}{}", remote, path)
+        } else {
+            format!("{}/{}", remote, path)
+        }
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(&self.config.binary);
+        if let Some(config) = &self.config.config {
+            command.arg("--config").arg(config);
+        }
+        command
+            .args(&self.config.args)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        command
+    }
+
+    // 运行 rclone，退出码不为 0 时返回 Error::Rclone
+    async fn run(&self, args: &[&str]) -> Result<Vec<u8>, Error> {
+        let output = self.command(args).output().await.context(IoSnafu)?;
+        check(&output)?;
+        Ok(output.stdout)
+    }
+}
+
+fn check(output: &std::process::Output) -> Result<(), Error> {
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(Error::Rclone {
+        code: output.status.code().unwrap_or(-1),
+        stderr: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+    })
+}
+
+fn not_found(e: &Error) -> bool {
+    matches!(e, Error::Rclone { code, .. } if *code == DIR_NOT_FOUND || *code == FILE_NOT_FOUND)
+}
+
+// rclone 输出的哈希都是十六进制，OneDrive 的 QuickXorHash 需要转换为 base64
+fn checksum(hashes: &std::collections::HashMap<String, String>) -> Option<Checksum> {
+    if let Some(sha256) = hashes.get("sha256") {
+        return Some(Checksum::Sha256(sha256.clone()));
+    }
+    let quickxor = hashes.get("quickxor")?;
+    let bytes = (0..quickxor.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(quickxor.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+    Some(Checksum::QuickXor(
+        base64::engine::general_purpose::STANDARD.encode(bytes),
+    ))
+}
+
+#[async_trait::async_trait]
+impl Target for Rclone {
+    // rclone rcat 从标准输入读取，不支持断点续传
+    async fn upload(&self, mut reader: Reader, size: u64, path: &Path) -> Result<(), Error> {
+        let remote = self.remote(path);
+        let mut child = self
+            .command(&["rcat", "--size", &size.to_string(), &remote])
+            .stdin(Stdio::piped())
+            .spawn()
+            .context(IoSnafu)?;
+
+        let mut stdin = child.stdin.take().unwrap();
+        let copied = tokio::io::copy(&mut reader, &mut stdin).await;
+        drop(stdin);
+
+        // rclone 提前退出时写入会失败，优先返回 rclone 的错误
+        let output = child.wait_with_output().await.context(IoSnafu)?;
+        check(&output)?;
+        let copied = copied.context(IoSnafu)?;
+        if copied != size {
+            return Err(Error::Upload {
+                error: format!("only {} of {} bytes sent to rclone", copied, size),
+            });
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, path: &Path) -> Result<(), Error> {
+        match self.run(&["deletefile", &self.remote(path)]).await {
+            Err(e) if !not_found(&e) => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    async fn stat(&self, path: &Path) -> Result<Option<Remote>, Error> {
+        let stdout = match self
+            .run(&["lsjson", "--stat", "--hash", &self.remote(path)])
+            .await
+        {
+            Ok(stdout) => stdout,
+            Err(e) if not_found(&e) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let entry: Entry = serde_json::from_slice(&stdout).map_err(|e| Error::Upload {
+            error: format!("Invalid lsjson output: {}", e),
+        })?;
+        Ok(Some(Remote {
+            size: entry.size,
+            checksum: checksum(&entry.hashes),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    use super::*;
+
+    // 模拟 rclone 的 version、rcat、lsjson 和 deletefile，远程路径形如 local:/path
+    const FAKE_RCLONE: &str = r#"#!/bin/sh
+cmd=$1
+shift
+case "$cmd" in
+version)
+    echo "rclone v1.68.0"
+    ;;
+rcat)
+    path=${3#local:}
+    mkdir -p "$(dirname "$path")"
+    cat > "$path"
+    ;;
+lsjson)
+    path=${3#local:}
+    [ -f "$path" ] || { echo "object not found" >&2; exit 3; }
+    printf '{"Size":%s,"Hashes":{"sha256":"%s"}}' "$(wc -c < "$path")" "$(sha256sum "$path" | cut -d' ' -f1)"
+    ;;
+deletefile)
+    path=${1#local:}
+    [ -f "$path" ] || { echo "object not found" >&2; exit 4; }
+    rm "$path"
+    ;;
+*)
+    echo "unknown command $cmd" >&2
+    exit 1
+    ;;
+esac
+"#;
+
+    #[tokio::test]
+    async fn test_rclone() {
+        let dir = std::env::temp_dir().join("mikan-subscriber-test-rclone");
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let binary = dir.join("rclone");
+        tokio::fs::write(&binary, FAKE_RCLONE).await.unwrap();
+        tokio::fs::set_permissions(&binary, std::fs::Permissions::from_mode(0o755))
+            .await
+            .unwrap();
+
+        let config = config::Rclone {
+            name: "test-rclone".into(),
+            remote: format!("local:{}", dir.join("remote").display()),
+            binary,
+            config: None,
+            args: Vec::new(),
+        };
+        let missing = config::Rclone {
+            binary: dir.join("missing"),
+            ..config.clone()
+        };
+        assert!(Rclone::new(missing).await.is_err());
+
+        let rclone = Rclone::new(config).await.unwrap();
+        let path = Path::new("dir/file.mkv");
+        let data = b"0123456789".to_vec();
+
+        rclone
+            .upload(Box::new(std::io::Cursor::new(data.clone())), 10, path)
+            .await
+            .unwrap();
+        assert_eq!(
+            tokio::fs::read(dir.join("remote/dir/file.mkv"))
+                .await
+                .unwrap(),
+            data
+        );
+
+        let remote = rclone.stat(path).await.unwrap().unwrap();
+        assert_eq!(remote.size, 10);
+        assert!(remote
+            .checksum
+            .unwrap()
+            .verify(Box::new(std::io::Cursor::new(data)))
+            .await
+            .unwrap());
+
+        rclone.delete(path).await.unwrap();
+        assert!(rclone.stat(path).await.unwrap().is_none());
+        // 已经不存在的文件视为删除成功
+        rclone.delete(path).await.unwrap();
+
+        let err = rclone.run(&["unknown"]).await.unwrap_err();
+        assert!(matches!(err, Error::Rclone { code: 1, .. }));
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[test]
+    fn test_checksum() {
+        let hashes = [("quickxor".to_owned(), "00".repeat(20))].into();
+        assert_eq!(
+            checksum(&hashes),
+            Some(Checksum::QuickXor("AAAAAAAAAAAAAAAAAAAAAAAAAAA=".into()))
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use std::{
+    io::{Read as _, Seek as _, Write as _},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use base64::Engine as _;
+use snafu::ResultExt;
+use ssh2::{FileStat, OpenFlags, OpenType, RenameFlags};
+
+use crate::{
+    store::{UploadSession, UploadSessions},
+    util::config::{self, SftpAuth},
+};
+
+use super::{
+    load_session, part_path, read_chunk, remove_session, save_session, session_key, skip, Checksum,
+    Error, IoSnafu, Reader, Remote, SshSnafu, Target, CHUNK_SIZE,
+};
+
+// SFTP 错误码 LIBSSH2_FX_NO_SUCH_FILE
+const NO_SUCH_FILE: i32 = 2;
+// 网络中断时阻塞的最长时间
+const TIMEOUT_MS: u32 = 60_000;
+// 服务器计算大文件的 SHA-256 期间没有输出，需要更长的等待时间
+const HASH_TIMEOUT_MS: u32 = 30 * 60_000;
+
+pub struct Sftp {
+    config: Arc<config::Sftp>,
+    sessions: Arc<UploadSessions>,
+    chunk_size: u64,
+}
+
+impl Sftp {
+    pub fn new(config: config::Sftp, sessions: Arc<UploadSessions>) -> Self {
+        Self {
+            config: Arc::new(config),
+            sessions,
+            chunk_size: CHUNK_SIZE,
+        }
+    }
+
+    // ssh2 只有同步接口，放到阻塞线程中执行
+    async fn blocking<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&config::Sftp) -> Result<T, Error> + Send + 'static,
+    ) -> Result<T, Error> {
+        let config = self.config.clone();
+        tokio::task::spawn_blocking(move || f(&config))
+            .await
+            .map_err(|e| Error::Upload {
+                error: e.to_string(),
+            })?
+    }
+}
+
+fn connect(config: &config::Sftp) -> Result<ssh2::Sftp, Error> {
+    session(config)?.sftp().context(SshSnafu)
+}
+
+fn session(config: &config::Sftp) -> Result<ssh2::Session, Error> {
+    let tcp = TcpStream::connect((config.host.as_str(), config.port)).context(IoSnafu)?;
+    let mut session = ssh2::Session::new().context(SshSnafu)?;
+    session.set_tcp_stream(tcp);
+    session.set_timeout(TIMEOUT_MS);
+    session.handshake().context(SshSnafu)?;
+
+    let key = fingerprint(
+        session
+            .host_key_hash(ssh2::HashType::Sha256)
+            .unwrap_or_default(),
+    );
+    if config.host_key.as_deref().map(str::trim) != Some(key.as_str()) {
+        return Err(Error::HostKey {
+            host: config.host.clone(),
+            fingerprint: key,
+        });
+    }
+
+    match &config.auth {
+        SftpAuth::Password { password } => session.userauth_password(&config.username, password),
+        SftpAuth::KeyFile {
+            private_key,
+            passphrase,
+        } => {
+            session.userauth_pubkey_file(&config.username, None, private_key, passphrase.as_deref())
+        }
+    }
+    .context(SshSnafu)?;
+
+    Ok(session)
+}
+
+// 在服务器上执行 sha256sum，只允许 SFTP 或没有该命令时返回 None
+fn sha256sum(session: &ssh2::Session, path: &Path) -> Option<Checksum> {
+    session.set_timeout(HASH_TIMEOUT_MS);
+    let output = exec(session, &format!("sha256sum -- {}", quote(path)));
+    session.set_timeout(TIMEOUT_MS);
+
+    output?
+        .split_whitespace()
+        .next()
+        .filter(|h| h.len() == 64 && h.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(|h| Checksum::Sha256(h.to_owned()))
+}
+
+// 执行命令并读取输出，退出码不为 0 时返回 None
+fn exec(session: &ssh2::Session, command: &str) -> Option<String> {
+    let mut channel = session.channel_session().ok()?;
+    channel.exec(command).ok()?;
+    let mut output = String::new();
+    channel.read_to_string(&mut output).ok()?;
+    channel.wait_close().ok()?;
+    (channel.exit_status().ok()? == 0).then_some(output)
+}
+
+// 单引号包裹路径，其中的单引号写成 '\''
+fn quote(path: &Path) -> String {
+    format!("'{}'", path.to_string_lossy().replace('\'', r"'\''"))
+}
+
+// 与 ssh-keygen -lf 输出的格式相同
+fn fingerprint(hash: &[u8]) -> String {
+    format!(
+        "SHA256:{}",
+        base64::engine::general_purpose::STANDARD_NO_PAD.encode(hash)
+    )
+}
+
+fn not_found(e: &ssh2::Error) -> bool {
+    e.code() == ssh2::ErrorCode::SFTP(NO_SUCH_FILE)
+}
+
+// 逐级创建目录，已经存在的跳过
+fn create_dirs(sftp: &ssh2::Sftp, dir: &Path) -> Result<(), Error> {
+    let mut current = PathBuf::new();
+    for component in dir.components() {
+        current.push(component);
+        if sftp.stat(&current).is_ok() {
+            continue;
+        }
+        if let Err(e) = sftp.mkdir(&current, 0o755) {
+            // 可能被同时上传的其他文件创建了
+            if !sftp.stat(&current).is_ok_and(|s| s.is_dir()) {
+                return Err(e).context(SshSnafu);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl Target for Sftp {
+    // 先分块写入 .part 文件，每写完一块记录位置，完成后再重命名
+    async fn upload(&self, mut reader: Reader, size: u64, path: &Path) -> Result<(), Error> {
+        let remote = self.config.root.join(path);
+        let part = part_path(&remote);
+        let key = session_key(&self.config.name, path);
+        let saved = load_session(&self.sessions, &key, size)?.map(|s| s.offset);
+
+        let (sftp, mut file, mut offset) = self
+            .blocking({
+                let remote = remote.clone();
+                let part = part.clone();
+                move |config| {
+                    let sftp = connect(config)?;
+                    if let Some(parent) = remote.parent() {
+                        create_dirs(&sftp, parent)?;
+                    }
+
+                    // 记录的位置之后可能还有没来得及记录的数据，以两者中较小的为准
+                    let written = sftp.stat(&part).ok().and_then(|s| s.size).unwrap_or(0);
+                    let offset = saved.map(|o| o.min(written)).unwrap_or(0);
+                    let mut file = sftp
+                        .open_mode(
+                            &part,
+                            OpenFlags::WRITE | OpenFlags::CREATE,
+                            0o644,
+                            OpenType::File,
+                        )
+                        .context(SshSnafu)?;
+                    file.setstat(FileStat {
+                        size: Some(offset),
+                        uid: None,
+                        gid: None,
+                        perm: None,
+                        atime: None,
+                        mtime: None,
+                    })
+                    .context(SshSnafu)?;
+                    file.seek(std::io::SeekFrom::Start(offset))
+                        .context(IoSnafu)?;
+                    Ok((sftp, file, offset))
+                }
+            })
+            .await?;
+        if offset > 0 {
+            tracing::info!("Resuming {} from {}", path.display(), offset);
+        }
+        skip(&mut reader, offset).await?;
+
+        while offset < size {
+            let len = self.chunk_size.min(size - offset);
+            let chunk = read_chunk(&mut reader, len).await?;
+            file = self
+                .blocking(move |_| {
+                    file.write_all(&chunk).context(IoSnafu)?;
+                    Ok(file)
+                })
+                .await?;
+
+            offset += len;
+            save_session(&self.sessions, &key, UploadSession::new(size, offset, None))?;
+        }
+
+        self.blocking(move |_| {
+            drop(file);
+            // 部分服务器不支持覆盖已有文件的重命名
+            if sftp.rename(&part, &remote, None).is_err() {
+                match sftp.unlink(&remote) {
+                    Err(e) if !not_found(&e) => return Err(e).context(SshSnafu),
+                    _ => {}
+                }
+                sftp.rename(&part, &remote, Some(RenameFlags::empty()))
+                    .context(SshSnafu)?;
+            }
+            Ok(())
+        })
+        .await?;
+
+        remove_session(&self.sessions, &key)
+    }
+
+    async fn delete(&self, path: &Path) -> Result<(), Error> {
+        let remote = self.config.root.join(path);
+        self.blocking(move |config| match connect(config)?.unlink(&remote) {
+            Err(e) if !not_found(&e) => Err(e).context(SshSnafu),
+            _ => Ok(()),
+        })
+        .await
+    }
+
+    // SFTP 没有提供校验值，能够执行命令时在服务器上计算 SHA-256，否则只比较大小
+    async fn stat(&self, path: &Path) -> Result<Option<Remote>, Error> {
+        let remote = self.config.root.join(path);
+        self.blocking(move |config| {
+            let session = session(config)?;
+            let sftp = session.sftp().context(SshSnafu)?;
+            let size = match sftp.stat(&remote) {
+                Ok(stat) => stat.size.unwrap_or_default(),
+                Err(e) if not_found(&e) => return Ok(None),
+                Err(e) => return Err(e).context(SshSnafu),
+            };
+            Ok(Some(Remote {
+                size,
+                checksum: sha256sum(&session, &remote),
+            }))
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint() {
+        let hash = (0..32u8).collect::<Vec<_>>();
+        assert_eq!(
+            fingerprint(&hash),
+            "SHA256:AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8"
+        );
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(
+            quote(Path::new("/srv/Frieren's Journey/01.mkv")),
+            r"'/srv/Frieren'\''s Journey/01.mkv'"
+        );
+    }
+}
@@ -0,0 +1,109 @@
+use std::{
+    future::Future as _,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, ReadBuf},
+    time::{Instant, Sleep},
+};
+
+use super::Reader;
+
+/// 令牌桶限速，多个 reader 共享同一个限速时总速率不超过限制
+#[derive(Debug)]
+pub struct Throttle {
+    bytes_per_sec: u64,
+    // 可用的字节数，为负表示已经透支
+    state: Mutex<(f64, Instant)>,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_sec: u64) -> Arc<Self> {
+        Arc::new(Self {
+            bytes_per_sec: bytes_per_sec.max(1),
+            state: Mutex::new((0.0, Instant::now())),
+        })
+    }
+
+    /// 在 reader 外包装限速
+    pub fn wrap(self: &Arc<Self>, reader: Reader) -> Reader {
+        Box::new(ThrottledReader {
+            inner: reader,
+            throttle: self.clone(),
+            sleep: None,
+        })
+    }
+
+    // 消耗读取的字节数，返回需要等待的时间
+    fn consume(&self, bytes: usize) -> Duration {
+        let rate = self.bytes_per_sec as f64;
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        // 最多积累一秒的额度，避免空闲后突发
+        state.0 = (state.0 + now.duration_since(state.1).as_secs_f64() * rate).min(rate);
+        state.1 = now;
+        state.0 -= bytes as f64;
+
+        if state.0 >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-state.0 / rate)
+        }
+    }
+}
+
+struct ThrottledReader {
+    inner: Reader,
+    throttle: Arc<Throttle>,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl AsyncRead for ThrottledReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if let Some(sleep) = self.sleep.as_mut() {
+            if sleep.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.sleep = None;
+        }
+
+        let filled = buf.filled().len();
+        let ret = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = ret {
+            let wait = self.throttle.consume(buf.filled().len() - filled);
+            if !wait.is_zero() {
+                self.sleep = Some(Box::pin(tokio::time::sleep(wait)));
+            }
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt as _;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_throttle() {
+        let throttle = Throttle::new(100_000);
+        let data = vec![0u8; 50_000];
+        let mut reader = throttle.wrap(Box::new(std::io::Cursor::new(data)));
+
+        let start = std::time::Instant::now();
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).await.unwrap();
+
+        assert_eq!(buf.len(), 50_000);
+        assert!(start.elapsed() >= Duration::from_millis(400));
+    }
+}
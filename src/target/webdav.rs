@@ -0,0 +1,531 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use snafu::ResultExt;
+use tokio::sync::OnceCell;
+use upload_backend::{backend::WebdavAuth, Backend};
+use url::Url;
+
+use crate::{
+    store::{UploadSession, UploadSessions},
+    util::reqwest::client,
+};
+
+use super::{
+    components, digest::Challenge, load_session, part_path, read_chunk, remove_session,
+    save_session, session_key, skip, Checksum, Error, Reader, Remote, RequestSnafu, Target,
+    CHUNK_SIZE,
+};
+
+pub struct Webdav {
+    name: String,
+    // 服务器不支持部分更新时使用 upload_backend 整体上传
+    backend: OnceCell<upload_backend::backend::Webdav>,
+    url: String,
+    auth: WebdavAuth,
+    // 摘要认证最近一次收到的质询
+    challenge: Mutex<Option<Challenge>>,
+    sessions: Arc<UploadSessions>,
+    chunk_size: u64,
+    partial_update: OnceCell<bool>,
+}
+
+impl Webdav {
+    pub async fn new(
+        name: &str,
+        auth: WebdavAuth,
+        url: &str,
+        sessions: Arc<UploadSessions>,
+    ) -> Result<Self, Error> {
+        let backend = upload_backend::backend::Webdav::new(auth.clone(), url)
+            .await
+            .map_err(|e| Error::Upload {
+                error: e.to_string(),
+            })?;
+
+        Ok(Self {
+            name: name.to_owned(),
+            backend: OnceCell::new_with(Some(backend)),
+            url: url.to_owned(),
+            auth,
+            challenge: Mutex::new(None),
+            sessions,
+            chunk_size: CHUNK_SIZE,
+            partial_update: OnceCell::new(),
+        })
+    }
+
+    fn file_url(&self, path: &Path) -> Result<Url, Error> {
+        let mut url = Url::parse(&self.url).map_err(|_| Error::Url {
+            url: self.url.clone(),
+        })?;
+        url.path_segments_mut()
+            .map_err(|_| Error::Url {
+                url: self.url.clone(),
+            })?
+            .pop_if_empty()
+            .extend(components(path));
+
+        Ok(url)
+    }
+
+    fn request(&self, method: reqwest::Method, url: Url) -> reqwest::RequestBuilder {
+        let request = client().request(method, url);
+        match &self.auth {
+            WebdavAuth::Basic(username, password) => request.basic_auth(username, Some(password)),
+            _ => request,
+        }
+    }
+
+    // 摘要认证需要先收到服务器的质询，返回 401 时按新的质询重新计算 Authorization 再试一次
+    async fn execute(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response, Error> {
+        let mut request = request.build().context(RequestSnafu)?;
+        let WebdavAuth::Digest(username, password) = &self.auth else {
+            return client().execute(request).await.context(RequestSnafu);
+        };
+
+        let retry = request.try_clone();
+        self.authorize(&mut request, username, password);
+        let res = client().execute(request).await.context(RequestSnafu)?;
+        if res.status() != reqwest::StatusCode::UNAUTHORIZED {
+            return Ok(res);
+        }
+        let challenge = res
+            .headers()
+            .get_all(reqwest::header::WWW_AUTHENTICATE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .find_map(Challenge::parse);
+        let (Some(challenge), Some(mut retry)) = (challenge, retry) else {
+            return Ok(res);
+        };
+
+        *self.challenge.lock().unwrap() = Some(challenge);
+        self.authorize(&mut retry, username, password);
+        client().execute(retry).await.context(RequestSnafu)
+    }
+
+    fn authorize(&self, request: &mut reqwest::Request, username: &str, password: &str) {
+        let mut challenge = self.challenge.lock().unwrap();
+        let Some(challenge) = challenge.as_mut() else {
+            return;
+        };
+        let url = request.url();
+        let uri = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_owned(),
+        };
+        let authorization =
+            challenge.authorization(username, password, request.method().as_str(), &uri);
+        if let Ok(value) = authorization.parse() {
+            request
+                .headers_mut()
+                .insert(reqwest::header::AUTHORIZATION, value);
+        }
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder, url: &Url) -> Result<(), Error> {
+        let res = self.execute(request).await?;
+        if !res.status().is_success() {
+            return Err(Error::Status {
+                status: res.status(),
+                url: url.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    // SabreDAV（Nextcloud、ownCloud 等）支持按范围写入文件，才能断点续传
+    async fn partial_update(&self) -> bool {
+        let ret = self
+            .partial_update
+            .get_or_try_init(|| async {
+                let url = self.file_url(Path::new(""))?;
+                let res = self
+                    .execute(self.request(reqwest::Method::OPTIONS, url))
+                    .await?;
+                let supported = res
+                    .headers()
+                    .get_all("DAV")
+                    .iter()
+                    .filter_map(|v| v.to_str().ok())
+                    .any(|v| v.contains("sabredav-partialupdate"));
+                Ok::<_, Error>(supported)
+            })
+            .await;
+
+        ret.copied().unwrap_or(false)
+    }
+
+    // 远程文件的响应头，不存在时返回 None
+    async fn head(&self, url: &Url) -> Result<Option<reqwest::header::HeaderMap>, Error> {
+        let res = self
+            .execute(self.request(reqwest::Method::HEAD, url.clone()))
+            .await?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !res.status().is_success() {
+            return Err(Error::Status {
+                status: res.status(),
+                url: url.to_string(),
+            });
+        }
+        Ok(Some(res.headers().clone()))
+    }
+
+    // 远程文件的大小，不存在时返回 None
+    async fn remote_size(&self, url: &Url) -> Result<Option<u64>, Error> {
+        Ok(self.head(url).await?.and_then(|h| content_length(&h)))
+    }
+
+    // 逐级创建上级目录，已经存在时服务器返回 405
+    async fn create_dirs(&self, path: &Path) -> Result<(), Error> {
+        let mut dir = std::path::PathBuf::new();
+        for name in path.parent().map(components).unwrap_or_default() {
+            dir.push(name);
+            let url = self.file_url(&dir)?;
+            let method = reqwest::Method::from_bytes(b"MKCOL").unwrap();
+            let res = self.execute(self.request(method, url.clone())).await?;
+            if !res.status().is_success() && res.status() != reqwest::StatusCode::METHOD_NOT_ALLOWED
+            {
+                return Err(Error::Status {
+                    status: res.status(),
+                    url: url.to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    async fn upload_full(&self, reader: Reader, size: u64, path: &Path) -> Result<(), Error> {
+        let backend = self
+            .backend
+            .get_or_try_init(|| upload_backend::backend::Webdav::new(self.auth.clone(), &self.url))
+            .await
+            .map_err(|e| Error::Upload {
+                error: e.to_string(),
+            })?;
+        backend
+            .upload(reader, size, path.to_owned())
+            .await
+            .map_err(|e| Error::Upload {
+                error: e.to_string(),
+            })
+    }
+}
+
+// HEAD 请求的 content_length() 总是 0，需要直接读取响应头
+fn content_length(headers: &reqwest::header::HeaderMap) -> Option<u64> {
+    headers
+        .get(reqwest::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+}
+
+// Nextcloud、ownCloud 的 OC-Checksum 形如 `SHA1:xxx MD5:xxx SHA256:xxx`，
+// rclone serve webdav --etag-hash SHA256 的 ETag 就是 SHA-256，其他服务器的 ETag 不是内容的哈希
+fn checksum(headers: &reqwest::header::HeaderMap) -> Option<Checksum> {
+    let oc = headers
+        .get("OC-Checksum")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| {
+            v.split_whitespace().find_map(|c| match c.split_once(':') {
+                Some((algorithm, value)) if algorithm.eq_ignore_ascii_case("SHA256") => {
+                    Some(value.to_owned())
+                }
+                _ => None,
+            })
+        });
+    let etag = headers
+        .get(reqwest::header::ETAG)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim_start_matches("W/").trim_matches('"'))
+        .filter(|v| v.len() == 64 && v.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(str::to_owned);
+
+    oc.or(etag).map(Checksum::Sha256)
+}
+
+#[async_trait::async_trait]
+impl Target for Webdav {
+    // 先分块写入 .part 文件，每写完一块记录位置，完成后再移动到目标路径
+    async fn upload(&self, mut reader: Reader, size: u64, path: &Path) -> Result<(), Error> {
+        if size <= self.chunk_size || !self.partial_update().await {
+            return self.upload_full(reader, size, path).await;
+        }
+
+        let url = self.file_url(path)?;
+        let part = self.file_url(&part_path(path))?;
+        let key = session_key(&self.name, path);
+        self.create_dirs(path).await?;
+
+        // 服务器上的 .part 文件可能比记录的位置长，从记录的位置覆盖写入
+        let mut offset = match (
+            load_session(&self.sessions, &key, size)?,
+            self.remote_size(&part).await?,
+        ) {
+            (Some(session), Some(remote)) => session.offset.min(remote),
+            _ => 0,
+        };
+        if offset > 0 {
+            tracing::info!("Resuming {} from {}", path.display(), offset);
+        }
+        skip(&mut reader, offset).await?;
+
+        while offset < size {
+            let len = self.chunk_size.min(size - offset);
+            let chunk = read_chunk(&mut reader, len).await?;
+            let request = if offset == 0 {
+                self.request(reqwest::Method::PUT, part.clone())
+            } else {
+                self.request(reqwest::Method::PATCH, part.clone())
+                    .header("Content-Type", "application/x-sabredav-partialupdate")
+                    .header(
+                        "X-Update-Range",
+                        format!("bytes={}-{}", offset, offset + len - 1),
+                    )
+            };
+            self.send(request.body(chunk), &part).await?;
+
+            offset += len;
+            save_session(&self.sessions, &key, UploadSession::new(size, offset, None))?;
+        }
+
+        let method = reqwest::Method::from_bytes(b"MOVE").unwrap();
+        let request = self
+            .request(method, part.clone())
+            .header("Destination", url.as_str())
+            .header("Overwrite", "T");
+        self.send(request, &part).await?;
+
+        remove_session(&self.sessions, &key)
+    }
+
+    async fn delete(&self, path: &Path) -> Result<(), Error> {
+        let url = self.file_url(path)?;
+        let res = self
+            .execute(self.request(reqwest::Method::DELETE, url.clone()))
+            .await?;
+
+        if res.status().is_success() || res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+        Err(Error::Status {
+            status: res.status(),
+            url: url.to_string(),
+        })
+    }
+
+    async fn stat(&self, path: &Path) -> Result<Option<Remote>, Error> {
+        let url = self.file_url(path)?;
+        let Some(headers) = self.head(&url).await? else {
+            return Ok(None);
+        };
+        let size = content_length(&headers).ok_or_else(|| Error::Upload {
+            error: format!("No Content-Length for {}", url),
+        })?;
+
+        Ok(Some(Remote {
+            size,
+            checksum: checksum(&headers),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        matchers::{header, header_exists, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+    use crate::target::temp_sessions;
+
+    fn webdav(
+        name: &str,
+        server: &MockServer,
+        auth: WebdavAuth,
+        sessions: Arc<UploadSessions>,
+    ) -> Webdav {
+        static INIT: std::sync::Once = std::sync::Once::new();
+        INIT.call_once(|| {
+            crate::util::reqwest::init_client(None).unwrap();
+        });
+        Webdav {
+            name: name.to_owned(),
+            backend: OnceCell::new(),
+            url: format!("{}/dav/", server.uri()),
+            auth,
+            challenge: Mutex::new(None),
+            sessions,
+            chunk_size: 4,
+            partial_update: OnceCell::new(),
+        }
+    }
+
+    async fn mock_server() -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("OPTIONS"))
+            .respond_with(
+                ResponseTemplate::new(200).insert_header("DAV", "1, 2, sabredav-partialupdate"),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("MKCOL"))
+            .and(path("/dav/dir"))
+            .respond_with(ResponseTemplate::new(405))
+            .mount(&server)
+            .await;
+        Mock::given(method("MOVE"))
+            .and(path("/dav/dir/file.mkv.part"))
+            .and(header(
+                "Destination",
+                format!("{}/dav/dir/file.mkv", server.uri()).as_str(),
+            ))
+            .respond_with(ResponseTemplate::new(201))
+            .expect(1)
+            .mount(&server)
+            .await;
+        server
+    }
+
+    fn patch(range: &str) -> Mock {
+        Mock::given(method("PATCH"))
+            .and(path("/dav/dir/file.mkv.part"))
+            .and(header("X-Update-Range", range))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+    }
+
+    #[tokio::test]
+    async fn test_upload_chunks() {
+        let server = mock_server().await;
+        Mock::given(method("HEAD"))
+            .and(path("/dav/dir/file.mkv.part"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/dav/dir/file.mkv.part"))
+            .respond_with(ResponseTemplate::new(201))
+            .expect(1)
+            .mount(&server)
+            .await;
+        patch("bytes=4-7").mount(&server).await;
+        patch("bytes=8-9").mount(&server).await;
+
+        let data = (0..10u8).collect::<Vec<_>>();
+        let (_dir, sessions) = temp_sessions();
+        webdav(
+            "test-webdav-chunks",
+            &server,
+            WebdavAuth::Anonymous,
+            sessions,
+        )
+        .upload(
+            Box::new(std::io::Cursor::new(data)),
+            10,
+            Path::new("dir/file.mkv"),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resume() {
+        let server = mock_server().await;
+        // 服务器上已经有 6 个字节，但只确认了 4 个
+        Mock::given(method("HEAD"))
+            .and(path("/dav/dir/file.mkv.part"))
+            .respond_with(ResponseTemplate::new(200).insert_header("Content-Length", "6"))
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .respond_with(ResponseTemplate::new(201))
+            .expect(0)
+            .mount(&server)
+            .await;
+        patch("bytes=4-7").mount(&server).await;
+        patch("bytes=8-9").mount(&server).await;
+
+        let (_dir, sessions) = temp_sessions();
+        let webdav = webdav(
+            "test-webdav-resume",
+            &server,
+            WebdavAuth::Anonymous,
+            sessions,
+        );
+        let key = session_key(&webdav.name, Path::new("dir/file.mkv"));
+        save_session(&webdav.sessions, &key, UploadSession::new(10, 4, None)).unwrap();
+
+        let data = (0..10u8).collect::<Vec<_>>();
+        webdav
+            .upload(
+                Box::new(std::io::Cursor::new(data)),
+                10,
+                Path::new("dir/file.mkv"),
+            )
+            .await
+            .unwrap();
+        assert!(load_session(&webdav.sessions, &key, 10).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_digest() {
+        let server = MockServer::start().await;
+        Mock::given(method("DELETE"))
+            .and(header_exists("Authorization"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(2)
+            .mount(&server)
+            .await;
+        // 第一次请求没有质询，之后复用同一个质询
+        Mock::given(method("DELETE"))
+            .respond_with(ResponseTemplate::new(401).insert_header(
+                "WWW-Authenticate",
+                r#"Digest realm="dav", qop="auth", nonce="abc""#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let (_dir, sessions) = temp_sessions();
+        let webdav = webdav(
+            "test-webdav-digest",
+            &server,
+            WebdavAuth::Digest("user".into(), "password".into()),
+            sessions,
+        );
+        webdav.delete(Path::new("a.mkv")).await.unwrap();
+        webdav.delete(Path::new("b.mkv")).await.unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        let authorization = requests[2].headers["Authorization"].to_str().unwrap();
+        assert!(authorization.starts_with(r#"Digest username="user", realm="dav""#));
+        assert!(authorization.contains(r#"uri="/dav/b.mkv""#));
+        assert!(authorization.contains("nc=00000002"));
+    }
+
+    #[test]
+    fn test_checksum() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("ETag", "\"5f3a-61b2c\"".parse().unwrap());
+        assert_eq!(checksum(&headers), None);
+
+        let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        headers.insert("ETag", format!("\"{}\"", sha256).parse().unwrap());
+        assert_eq!(checksum(&headers), Some(Checksum::Sha256(sha256.into())));
+
+        headers.insert(
+            "OC-Checksum",
+            "SHA1:a9993e364706816aba3e25717850c26c9cd0d89d SHA256:abc"
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(checksum(&headers), Some(Checksum::Sha256("abc".into())));
+    }
+}
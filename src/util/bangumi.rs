@@ -0,0 +1,161 @@
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+
+use crate::store;
+
+use super::reqwest::client;
+
+const USER_AGENT: &str = "Chikage0o0/mikan-subscriber";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Episodes {
+    pub data: Vec<Episode>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Episode {
+    pub name: String,
+    pub name_cn: String,
+    pub sort: u32,
+    pub ep: u32,
+    #[serde(default)]
+    pub airdate: String,
+    #[serde(default)]
+    pub desc: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subject {
+    pub id: u64,
+    pub name: String,
+    pub name_cn: String,
+    #[serde(default)]
+    pub summary: String,
+    #[serde(default)]
+    pub date: Option<String>,
+    #[serde(default)]
+    pub rating: Rating,
+    #[serde(default)]
+    pub tags: Vec<Tag>,
+    #[serde(default)]
+    pub images: Option<Images>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Rating {
+    #[serde(default)]
+    pub score: f32,
+    #[serde(default)]
+    pub total: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tag {
+    pub name: String,
+    pub count: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Images {
+    pub large: String,
+    pub common: String,
+}
+
+impl Episodes {
+    pub fn find(&self, episode: u32) -> Option<&Episode> {
+        self.data
+            .iter()
+            .find(|i| i.ep == episode || i.sort == episode)
+    }
+}
+
+/// 获取番组的正片剧集列表，优先使用缓存
+pub async fn episodes(subject_id: u64) -> Result<Episodes, Error> {
+    let cache = store::Db::get_cache().context(DbSnafu)?;
+    if let Some(episodes) = cache.get_episodes(subject_id).context(DbSnafu)? {
+        return Ok(episodes);
+    }
+
+    let episodes = client()
+        .get(format!(
+            "https://api.bgm.tv/v0/episodes?subject_id={}&type=0&limit=1000",
+            subject_id
+        ))
+        .header("User-Agent", USER_AGENT)
+        .send()
+        .await
+        .context(RequestSnafu)?
+        .error_for_status()
+        .context(RequestSnafu)?
+        .json::<Episodes>()
+        .await
+        .context(RequestSnafu)?;
+
+    cache
+        .insert_episodes(subject_id, episodes.clone())
+        .context(DbSnafu)?;
+
+    Ok(episodes)
+}
+
+/// 获取番组的简介、评分、标签等信息，优先使用缓存
+pub async fn subject(subject_id: u64) -> Result<Subject, Error> {
+    let cache = store::Db::get_cache().context(DbSnafu)?;
+    if let Some(subject) = cache.get_subject(subject_id).context(DbSnafu)? {
+        return Ok(subject);
+    }
+
+    let subject = client()
+        .get(format!("https://api.bgm.tv/v0/subjects/{}", subject_id))
+        .header("User-Agent", USER_AGENT)
+        .send()
+        .await
+        .context(RequestSnafu)?
+        .error_for_status()
+        .context(RequestSnafu)?
+        .json::<Subject>()
+        .await
+        .context(RequestSnafu)?;
+
+    cache.insert_subject(subject.clone()).context(DbSnafu)?;
+
+    Ok(subject)
+}
+
+/// 下载番组的海报
+pub async fn poster(subject: &Subject) -> Result<Option<Vec<u8>>, Error> {
+    let Some(images) = &subject.images else {
+        return Ok(None);
+    };
+    let url = if images.large.is_empty() {
+        &images.common
+    } else {
+        &images.large
+    };
+    if url.is_empty() {
+        return Ok(None);
+    }
+
+    let poster = client()
+        .get(url)
+        .header("User-Agent", USER_AGENT)
+        .send()
+        .await
+        .context(RequestSnafu)?
+        .error_for_status()
+        .context(RequestSnafu)?
+        .bytes()
+        .await
+        .context(RequestSnafu)?;
+
+    Ok(Some(poster.to_vec()))
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error requesting bangumi: {}", source))]
+    Request { source: reqwest::Error },
+
+    #[snafu(display("Error loading DB: {}", source))]
+    Db { source: redb::Error },
+}
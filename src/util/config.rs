@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::{collections::HashMap, path::PathBuf};
 
 use config::{Config, ConfigError, Environment, File};
 use serde::{ser::SerializeMap as _, Deserialize, Serialize};
@@ -7,11 +7,17 @@ use upload_backend::backend;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Storage {
     Local {
+        #[serde(default)]
+        name: Option<String>,
         root: PathBuf,
+        #[serde(default)]
+        mode: LocalMode,
     },
     Onedrive {
         name: String,
         client_id: String,
+        /// 使用 onedrive-login 设备码登录时应用需要是公共客户端，此处留空
+        #[serde(default)]
         client_secret: String,
         root: PathBuf,
         api_type: upload_backend::backend::OnedriveApiType,
@@ -21,6 +27,96 @@ pub enum Storage {
         url: String,
         auth: WebdavAuth,
     },
+    S3(S3),
+    Sftp(Sftp),
+    Rclone(Rclone),
+}
+
+/// S3 兼容的对象存储，如 AWS S3、MinIO、Cloudflare R2
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct S3 {
+    pub name: String,
+    /// 如 https://s3.us-east-1.amazonaws.com、http://127.0.0.1:9000
+    pub endpoint: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    pub bucket: String,
+    /// 对象名的前缀，如 anime
+    #[serde(default)]
+    pub prefix: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// 使用 endpoint/bucket/key 的地址，MinIO 需要开启
+    #[serde(default)]
+    pub path_style: bool,
+}
+
+fn default_region() -> String {
+    "us-east-1".into()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sftp {
+    pub name: String,
+    pub host: String,
+    #[serde(default = "default_sftp_port")]
+    pub port: u16,
+    pub username: String,
+    pub auth: SftpAuth,
+    /// 服务器公钥的指纹，如 `SHA256:...`，与 `ssh-keygen -lf` 的输出相同，未设置时拒绝连接
+    #[serde(default)]
+    pub host_key: Option<String>,
+    pub root: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SftpAuth {
+    Password {
+        password: String,
+    },
+    KeyFile {
+        private_key: PathBuf,
+        #[serde(default)]
+        passphrase: Option<String>,
+    },
+}
+
+fn default_sftp_port() -> u16 {
+    22
+}
+
+/// 通过 rclone 上传到它支持的任意存储，如 Google Drive、pCloud
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rclone {
+    pub name: String,
+    /// rclone 中配置的远程路径，如 `gdrive:anime`
+    pub remote: String,
+    #[serde(default = "default_rclone_binary")]
+    pub binary: PathBuf,
+    /// rclone.conf 的路径，默认使用 rclone 自己的配置
+    #[serde(default)]
+    pub config: Option<PathBuf>,
+    /// 附加的参数，如 `--drive-chunk-size 64M`
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+fn default_rclone_binary() -> PathBuf {
+    "rclone".into()
+}
+
+/// 本地存储放置文件的方式，与下载目录不在同一文件系统时都会退回复制
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum LocalMode {
+    #[default]
+    Copy,
+    /// 硬链接，不占用额外空间，做种结束后删除下载目录中的文件即可
+    Hardlink,
+    /// 写时复制（btrfs、xfs 等），之后修改任一文件都不会影响另一个
+    Reflink,
+    /// 移动到媒体库，并在原位置留下符号链接继续做种
+    Move,
 }
 
 #[derive(Debug, Clone)]
@@ -85,23 +181,354 @@ pub struct Download {
     pub upnp: bool,
     pub download_port: u16,
     pub threads: u16,
+    /// 最短做种时间
     pub seed_hours: f32,
-    pub max_download_hours: f32,
+    #[serde(default)]
+    pub seeding: Seeding,
+    /// 下载的最长时间，不设置时只按停滞判断
+    #[serde(default)]
+    pub max_download_hours: Option<f32>,
+    /// 这段时间内既没有下载到数据也没有新的 peer 连接时视为停滞
+    #[serde(default = "default_stall_minutes")]
+    pub stall_minutes: u64,
+    /// 下载失败后按指数退避重试，超过次数后改为下载同一集的其他版本
+    #[serde(default = "default_download_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_download_retry_secs")]
+    pub retry_base_secs: u64,
+    #[serde(default = "default_retry_max_secs")]
+    pub retry_max_secs: u64,
+    /// rqbit 会话的保存目录，重启后继续下载和做种，默认为 tmp_dir 下的 .session
+    #[serde(default)]
+    pub state_dir: Option<PathBuf>,
+}
+
+/// 达到最短做种时间后，满足任一条件即停止做种；都没有设置时到达最短时间即停止
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Seeding {
+    /// 上传量与种子大小之比
+    #[serde(default)]
+    pub ratio: Option<f32>,
+    /// 其他做种者达到该数量，人少的种子会一直做种到分享率或最长时间。
+    /// 做种者数量通过磁力链接中的 tracker 查询，查询失败时视为未达到
+    #[serde(default)]
+    pub min_seeders: Option<usize>,
+    /// 最长做种时间
+    #[serde(default)]
+    pub max_hours: Option<f32>,
+    /// 做种文件的总大小上限，超出时先删除最早完成的
+    #[serde(default)]
+    pub disk_budget_gb: Option<f32>,
+}
+
+fn default_stall_minutes() -> u64 {
+    30
+}
+
+fn default_download_attempts() -> u32 {
+    3
+}
+
+fn default_download_retry_secs() -> u64 {
+    30 * 60
+}
+
+impl Download {
+    pub fn state_dir(&self) -> PathBuf {
+        self.state_dir
+            .clone()
+            .unwrap_or_else(|| self.tmp_dir.join(".session"))
+    }
+}
+
+/// 一个蜜柑订阅源，每个订阅源有独立的轮询间隔和上传目标
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subscribe {
+    pub name: String,
+    pub url: String,
+    #[serde(default = "default_interval_minutes")]
+    pub interval_minutes: u64,
+    /// 上传目标的名称，为空时上传到所有存储
+    #[serde(default)]
+    pub storage: Vec<String>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_interval_minutes() -> u64 {
+    10
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+// 旧版本的配置中 subscribe 是单个订阅地址，作为名为 default 的订阅源
+fn deserialize_subscribe<'de, D>(deserializer: D) -> Result<Vec<Subscribe>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Subscribes {
+        Url(String),
+        List(Vec<Subscribe>),
+    }
+
+    Ok(match Subscribes::deserialize(deserializer)? {
+        Subscribes::Url(url) => vec![Subscribe {
+            name: "default".into(),
+            url,
+            interval_minutes: default_interval_minutes(),
+            storage: vec![],
+            enabled: default_enabled(),
+        }],
+        Subscribes::List(subscribe) => subscribe,
+    })
+}
+
+/// 过滤规则，`bangumi_tv_id` 为空时对所有动画生效
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Rule {
+    pub name: String,
+    #[serde(default)]
+    pub bangumi_tv_id: Option<u64>,
+    /// 标题需要匹配其中任意一个正则
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// 标题匹配其中任意一个正则则拒绝
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// 只接受这些字幕组
+    #[serde(default)]
+    pub subgroups: Vec<String>,
+    #[serde(default)]
+    pub exclude_subgroups: Vec<String>,
+    /// 只接受这些分辨率，如 1080、2160
+    #[serde(default)]
+    pub resolutions: Vec<u32>,
+    /// 标题需要包含其中任意一个语言关键字，如 CHS、简体
+    #[serde(default)]
+    pub languages: Vec<String>,
+}
+
+/// 同一集有多个版本时的偏好，越靠前越优先
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Preference {
+    #[serde(default)]
+    pub subgroups: Vec<String>,
+    #[serde(default)]
+    pub resolutions: Vec<u32>,
+    #[serde(default)]
+    pub codecs: Vec<String>,
+    #[serde(default)]
+    pub languages: Vec<String>,
+}
+
+/// 上传路径的布局，`template` 不为空时忽略 `preset`
+///
+/// 模板中可用的变量：`{title}`、`{season}`、`{episode}`、`{episode_name}`、`{year}`、
+/// `{quarter}`、`{quarter_month}`、`{weekday}`、`{subgroup}`、`{resolution}`、`{ext}`，
+/// 数字可以用 `{episode:02}` 补零
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Layout {
+    #[serde(default)]
+    pub preset: LayoutPreset,
+    #[serde(default)]
+    pub template: Option<String>,
+    /// 在视频旁边生成 tvshow.nfo、单集 nfo 和 poster.jpg
+    #[serde(default)]
+    pub metadata: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub enum LayoutPreset {
+    /// 2024年4月/星期三/Title/02 - Name.mkv
+    #[default]
+    Quarter,
+    /// Title (2024)/Season 01/Title - S01E02 - Name.mkv
+    MediaServer,
+}
+
+/// 需要上传的文件类型，扩展名不区分大小写
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Files {
+    #[serde(default = "default_videos")]
+    pub videos: Vec<String>,
+    /// 字幕会被重命名为与视频相同的名称
+    #[serde(default = "default_subtitles")]
+    pub subtitles: Vec<String>,
+    /// 字体上传到视频所在目录的 fonts 文件夹
+    #[serde(default = "default_fonts")]
+    pub fonts: Vec<String>,
+    /// 跳过这些目录中的文件，如特典、扫图，目录名不区分大小写
+    #[serde(default = "default_exclude_dirs")]
+    pub exclude_dirs: Vec<String>,
+}
+
+impl Default for Files {
+    fn default() -> Self {
+        Self {
+            videos: default_videos(),
+            subtitles: default_subtitles(),
+            fonts: default_fonts(),
+            exclude_dirs: default_exclude_dirs(),
+        }
+    }
+}
+
+fn default_videos() -> Vec<String> {
+    vec!["mkv".into(), "mp4".into()]
+}
+
+fn default_subtitles() -> Vec<String> {
+    vec!["ass".into(), "ssa".into(), "srt".into(), "vtt".into()]
+}
+
+fn default_fonts() -> Vec<String> {
+    vec!["ttf".into(), "otf".into(), "ttc".into()]
+}
+
+fn default_exclude_dirs() -> Vec<String> {
+    ["SPs", "SP", "Bonus", "Extras", "Scans", "CDs"]
+        .map(String::from)
+        .into()
+}
+
+/// 上传失败后按指数退避重试，超过最大次数后放弃该目标
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Upload {
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_retry_base_secs")]
+    pub retry_base_secs: u64,
+    #[serde(default = "default_retry_max_secs")]
+    pub retry_max_secs: u64,
+    /// 所有目标同时进行的上传数
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    /// 每个目标同时进行的上传数
+    #[serde(default = "default_target_concurrency")]
+    pub target_concurrency: usize,
+    /// 所有上传共享的速率限制，单位为字节每秒
+    #[serde(default)]
+    pub bytes_per_sec: Option<u64>,
+    /// 按存储名称覆盖的限制
+    #[serde(default)]
+    pub targets: HashMap<String, TargetLimit>,
+    /// 远程已经存在同名文件时的处理方式
+    #[serde(default)]
+    pub existing: Existing,
+    /// 目标不提供校验值时只比较大小也视为校验通过，只上传到这些目标的部分上传任务也按做种策略删除
+    #[serde(default)]
+    pub size_only: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Existing {
+    /// 大小和校验值相同时跳过，不同时覆盖
+    #[default]
+    Skip,
+    /// 总是重新上传并覆盖
+    Overwrite,
+    /// 大小和校验值相同时跳过，不同时在文件名后加上 (1)、(2) 等后缀
+    Rename,
+}
+
+impl Default for Upload {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_max_attempts(),
+            retry_base_secs: default_retry_base_secs(),
+            retry_max_secs: default_retry_max_secs(),
+            concurrency: default_concurrency(),
+            target_concurrency: default_target_concurrency(),
+            bytes_per_sec: None,
+            targets: HashMap::new(),
+            existing: Existing::default(),
+            size_only: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TargetLimit {
+    #[serde(default)]
+    pub concurrency: Option<usize>,
+    #[serde(default)]
+    pub bytes_per_sec: Option<u64>,
+}
+
+fn default_concurrency() -> usize {
+    4
+}
+
+fn default_target_concurrency() -> usize {
+    2
+}
+
+fn default_max_attempts() -> u32 {
+    5
+}
+
+fn default_retry_base_secs() -> u64 {
+    60
+}
+
+fn default_retry_max_secs() -> u64 {
+    60 * 60 * 6
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Settings {
     pub storage: Vec<Storage>,
-    pub subscribe: String,
+    #[serde(deserialize_with = "deserialize_subscribe")]
+    pub subscribe: Vec<Subscribe>,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    #[serde(default)]
+    pub preference: Preference,
+    #[serde(default)]
+    pub layout: Layout,
+    #[serde(default)]
+    pub files: Files,
+    #[serde(default)]
+    pub upload: Upload,
     pub download: Download,
     pub proxy: Option<String>,
     pub llama: Option<Llama>,
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Llama {
+    #[serde(default)]
+    pub provider: LlmProvider,
     pub model: String,
     pub url: String,
+    #[serde(default)]
     pub token: String,
+    #[serde(default = "default_llm_timeout_secs")]
+    pub timeout_secs: u64,
+    #[serde(default = "default_llm_retries")]
+    pub retries: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub enum LlmProvider {
+    /// url 为完整的 chat/completions 地址
+    #[default]
+    OpenAI,
+    /// url 为 Ollama 服务地址
+    Ollama,
+    /// url 为 llama.cpp server 地址
+    LlamaCpp,
+}
+
+fn default_llm_timeout_secs() -> u64 {
+    60
+}
+
+fn default_llm_retries() -> u32 {
+    2
 }
 
 impl Settings {
@@ -133,7 +560,11 @@ mod tests {
     fn test_settings_file() {
         let settings = Settings {
             storage: vec![
-                Storage::Local { root: "d".into() },
+                Storage::Local {
+                    name: Some("local".into()),
+                    root: "d".into(),
+                    mode: LocalMode::Hardlink,
+                },
                 Storage::Onedrive {
                     name: "name".into(),
                     client_id: "client_id".into(),
@@ -146,24 +577,135 @@ mod tests {
                     url: "url".into(),
                     auth: WebdavAuth(backend::WebdavAuth::Basic("user".into(), "pass".into())),
                 },
+                Storage::S3(S3 {
+                    name: "minio".into(),
+                    endpoint: "http://127.0.0.1:9000".into(),
+                    region: default_region(),
+                    bucket: "anime".into(),
+                    prefix: "library".into(),
+                    access_key_id: "access_key_id".into(),
+                    secret_access_key: "secret_access_key".into(),
+                    path_style: true,
+                }),
+                Storage::Sftp(Sftp {
+                    name: "nas".into(),
+                    host: "192.168.1.2".into(),
+                    port: default_sftp_port(),
+                    username: "user".into(),
+                    auth: SftpAuth::KeyFile {
+                        private_key: "/home/user/.ssh/id_ed25519".into(),
+                        passphrase: None,
+                    },
+                    host_key: Some("SHA256:nThbg6kXUpJWGl7E1IGOCspRomTxdCARLviKw6E5SY8".into()),
+                    root: "/volume1/anime".into(),
+                }),
+                Storage::Rclone(Rclone {
+                    name: "gdrive".into(),
+                    remote: "gdrive:anime".into(),
+                    binary: default_rclone_binary(),
+                    config: None,
+                    args: vec!["--drive-chunk-size".into(), "64M".into()],
+                }),
             ],
-            subscribe: "https://example.com".to_string(),
+            subscribe: vec![Subscribe {
+                name: "default".into(),
+                url: "https://example.com".into(),
+                interval_minutes: 10,
+                storage: vec!["local".into(), "name".into()],
+                enabled: true,
+            }],
+            rules: vec![Rule {
+                name: "no-720p".into(),
+                bangumi_tv_id: None,
+                exclude: vec![r"(?i)\b720p\b".into(), "合集".into()],
+                languages: vec!["CHS".into(), "简".into()],
+                ..Default::default()
+            }],
+            preference: Preference {
+                subgroups: vec!["Sakurato".into(), "LoliHouse".into()],
+                resolutions: vec![1080, 2160],
+                codecs: vec!["HEVC".into(), "AVC".into()],
+                languages: vec!["简日".into(), "CHS".into()],
+            },
+            layout: Layout {
+                preset: LayoutPreset::MediaServer,
+                template: None,
+                metadata: true,
+            },
+            files: Files::default(),
+            upload: Upload {
+                bytes_per_sec: Some(10 * 1024 * 1024),
+                targets: HashMap::from([(
+                    "name".into(),
+                    TargetLimit {
+                        concurrency: Some(1),
+                        bytes_per_sec: Some(2 * 1024 * 1024),
+                    },
+                )]),
+                existing: Existing::Rename,
+                ..Default::default()
+            },
             download: Download {
                 tmp_dir: "tmp".into(),
                 upnp: false,
                 download_port: 6881,
                 threads: 5,
                 seed_hours: 1.0,
-                max_download_hours: 24.0,
+                seeding: Seeding {
+                    ratio: Some(1.0),
+                    min_seeders: Some(5),
+                    max_hours: Some(72.0),
+                    disk_budget_gb: Some(100.0),
+                },
+                max_download_hours: Some(24.0),
+                stall_minutes: 30,
+                max_attempts: 3,
+                retry_base_secs: 1800,
+                retry_max_secs: 21600,
+                state_dir: None,
             },
             proxy: Some("socks5://127.0.0.1:1080".to_string()),
             llama: Some(Llama {
+                provider: LlmProvider::OpenAI,
                 model: "model".into(),
                 url: "url".into(),
                 token: "token".into(),
+                timeout_secs: 60,
+                retries: 2,
             }),
         };
 
         settings.save_to_file(SETTINGS).unwrap();
     }
+
+    #[test]
+    fn test_legacy_subscribe() {
+        let path = std::env::temp_dir().join("settings_legacy_subscribe.json");
+        std::fs::write(
+            &path,
+            r#"{
+                "storage": [],
+                "subscribe": "https://example.com",
+                "download": {
+                    "tmp_dir": "tmp",
+                    "upnp": false,
+                    "download_port": 6881,
+                    "threads": 5,
+                    "seed_hours": 1.0,
+                    "max_download_hours": 24.0
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let settings = Settings::load_from_file(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(settings.subscribe.len(), 1);
+        assert_eq!(settings.subscribe[0].name, "default");
+        assert_eq!(settings.subscribe[0].url, "https://example.com");
+        assert_eq!(settings.subscribe[0].interval_minutes, 10);
+        assert!(settings.subscribe[0].storage.is_empty());
+        assert!(settings.subscribe[0].enabled);
+    }
 }
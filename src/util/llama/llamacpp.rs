@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use snafu::ResultExt;
+
+use crate::util::reqwest::client;
+
+use super::{check_status, openai::Response, ChatCompletionMessage, Error, Provider, RequestSnafu};
+
+/// llama.cpp server，url 为服务地址，如 http://localhost:8080
+pub struct LlamaCpp {
+    url: String,
+    token: String,
+}
+
+impl LlamaCpp {
+    pub fn new(url: &str, token: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_owned(),
+            token: token.to_owned(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for LlamaCpp {
+    async fn chat(
+        &self,
+        messages: &[ChatCompletionMessage],
+        schema: &serde_json::Value,
+        timeout: Duration,
+    ) -> Result<String, Error> {
+        // llama.cpp 会将 json_schema 转换为语法约束
+        let chat_req = serde_json::json!({
+            "messages": messages,
+            "temperature": 0.01,
+            "json_schema": schema
+        });
+
+        let mut req = client()
+            .post(format!("{}/v1/chat/completions", self.url))
+            .timeout(timeout)
+            .json(&chat_req);
+        if !self.token.is_empty() {
+            req = req.bearer_auth(&self.token);
+        }
+        let res = req.send().await.context(RequestSnafu)?;
+        let res = check_status(res)
+            .await?
+            .json::<Response>()
+            .await
+            .context(RequestSnafu)?;
+
+        let choice = res.choices.into_iter().next().ok_or(Error::NoChoice)?;
+        Ok(choice.message.content)
+    }
+}
@@ -0,0 +1,346 @@
+mod llamacpp;
+mod ollama;
+mod openai;
+
+use std::{sync::OnceLock, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use snafu::Snafu;
+
+use super::config::{self, LlmProvider};
+
+static LLAMA: OnceLock<Llama> = OnceLock::new();
+
+/// 大模型服务，不同的服务使用各自的接口约束输出为 JSON
+#[async_trait::async_trait]
+pub trait Provider: Send + Sync {
+    async fn chat(
+        &self,
+        messages: &[ChatCompletionMessage],
+        schema: &serde_json::Value,
+        timeout: Duration,
+    ) -> Result<String, Error>;
+}
+
+pub struct Llama {
+    provider: Box<dyn Provider>,
+    timeout: Duration,
+    retries: u32,
+}
+
+impl Llama {
+    pub fn get() -> Option<&'static Llama> {
+        LLAMA.get()
+    }
+
+    pub fn init(setting: &config::Llama) -> Result<(), String> {
+        LLAMA
+            .set(Self::new(setting))
+            .map_err(|_| "Llama has been initialized".to_string())?;
+
+        Ok(())
+    }
+
+    fn new(setting: &config::Llama) -> Self {
+        let provider: Box<dyn Provider> = match setting.provider {
+            LlmProvider::OpenAI => Box::new(openai::OpenAI::new(
+                &setting.model,
+                &setting.url,
+                &setting.token,
+            )),
+            LlmProvider::Ollama => Box::new(ollama::Ollama::new(&setting.model, &setting.url)),
+            LlmProvider::LlamaCpp => {
+                Box::new(llamacpp::LlamaCpp::new(&setting.url, &setting.token))
+            }
+        };
+
+        Self {
+            provider,
+            timeout: Duration::from_secs(setting.timeout_secs),
+            retries: setting.retries,
+        }
+    }
+
+    pub async fn decode(&self, filename: &str) -> Result<ContentResponse, Error> {
+        let mut messages = vec![ChatCompletionMessage {
+            role: ChatCompletionMessageRole::System,
+            content: Some(SYSTEM.to_string()),
+        }];
+        for (user, assistant) in EXAMPLES {
+            messages.push(ChatCompletionMessage {
+                role: ChatCompletionMessageRole::User,
+                content: Some(user.to_string()),
+            });
+            messages.push(ChatCompletionMessage {
+                role: ChatCompletionMessageRole::Assistant,
+                content: Some(assistant.to_string()),
+            });
+        }
+        messages.push(ChatCompletionMessage {
+            role: ChatCompletionMessageRole::User,
+            content: Some(filename.to_string()),
+        });
+
+        let schema = schema();
+        let mut attempt = 0;
+        loop {
+            let ret = self
+                .provider
+                .chat(&messages, &schema, self.timeout)
+                .await
+                .and_then(|content| extract_json(&content));
+
+            match ret {
+                Ok(content) => return Ok(content),
+                Err(e) if attempt < self.retries => {
+                    tracing::warn!("Error decoding {}, retrying: {}", filename, e);
+                    // 重试次数来自配置，限制指数避免溢出，最长等待 32 秒
+                    let delay = 500u64 << attempt.min(6);
+                    tokio::time::sleep(Duration::from_millis(delay)).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+// 模型有时会在 JSON 外包裹代码块或者附带说明，取出其中第一个合法的对象
+fn extract_json(content: &str) -> Result<ContentResponse, Error> {
+    if let Ok(ret) = serde_json::from_str(content.trim()) {
+        return Ok(ret);
+    }
+
+    for (start, _) in content.match_indices('{') {
+        let mut stream =
+            serde_json::Deserializer::from_str(&content[start..]).into_iter::<ContentResponse>();
+        if let Some(Ok(ret)) = stream.next() {
+            return Ok(ret);
+        }
+    }
+
+    Err(Error::NoContent {
+        content: content.to_owned(),
+    })
+}
+
+fn schema() -> serde_json::Value {
+    serde_json::json!({
+        "type": "object",
+        "properties": {
+            "title": { "type": "string" },
+            "season": { "type": "integer" },
+            "episode": { "type": "integer" }
+        },
+        "required": ["title", "season", "episode"],
+        "additionalProperties": false
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContentResponse {
+    pub title: String,
+    pub season: u32,
+    pub episode: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatCompletionMessage {
+    role: ChatCompletionMessageRole,
+    content: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ChatCompletionMessageRole {
+    System,
+    User,
+    Assistant,
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
+pub enum Error {
+    #[snafu(display("Request error: {}", source))]
+    Request { source: reqwest::Error },
+
+    #[snafu(display("Unexpected status {}: {}", status, body))]
+    Status {
+        status: reqwest::StatusCode,
+        body: String,
+    },
+
+    #[snafu(display("No choice in response"))]
+    NoChoice,
+
+    #[snafu(display("No content in {}", content))]
+    NoContent { content: String },
+}
+
+// 检查状态码，失败时带上返回的内容方便排查
+async fn check_status(res: reqwest::Response) -> Result<reqwest::Response, Error> {
+    if res.status().is_success() {
+        return Ok(res);
+    }
+
+    Err(Error::Status {
+        status: res.status(),
+        body: res.text().await.unwrap_or_default(),
+    })
+}
+
+const SYSTEM: &str = r#"Please parse the following animation file names into JSON objects containing the keys "title," "season," and "episode." When the file name contains both a Chinese title and an alphabetic title, prefer the alphabetic one. Seasons default to 1 when there is no explicit identifier. Ensure the accuracy of your parsing results."#;
+
+const EXAMPLES: [(&str, &str); 6] = [
+    (
+        "[Billion Meta Lab] 恋语轻唱 Sasayaku You ni Koi wo Utau [02][1080][CHS].mp4",
+        r#"{"title": "Sasayaku You ni Koi wo Utau", "season": 1, "episode": 2}"#,
+    ),
+    (
+        "[Up to 21°C] 永生 第三季 - 06 (B-Global Donghua 1920x1080 HEVC AAC MKV) [9F7CAB79].mkv",
+        r#"{"title": "永生", "season": 3, "episode": 6}"#,
+    ),
+    (
+        "[GJ.Y] 我内心危险的东西2 - 05 (B-Global 1920x1080 HEVC AAC MKV) [60D8C635].mkv",
+        r#"{"title": "我内心危险的东西", "season": 2, "episode": 5}"#,
+    ),
+    (
+        "[Up to 21°C] Shinigami Bocchan to Kuro Maid 3rd Season - 30 (CR 1920x1080 AVC AAC MKV) [CE516DAA].mkv",
+        r#"{"title": "Shinigami Bocchan to Kuro Maid", "season": 3, "episode": 30}"#,
+    ),
+    (
+        "[Sakurato] Dungeon Meshi [17v2][AVC-8bit 1080p AAC][CHT].mp4",
+        r#"{"title": "Dungeon Meshi", "season": 1, "episode": 17}"#,
+    ),
+    (
+        "[Up to 21°C] Mushoku Tensei II -  Isekai Ittara Honki Dasu Part 2 - 18 (ABEMA 1920x1080 AVC AAC MP4) [05628308].mp4",
+        r#"{"title": "Mushoku Tensei II - Isekai Ittara Honki Dasu Part 2", "season": 2, "episode": 18}"#,
+    ),
+];
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        matchers::{body_partial_json, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use crate::util::{self, reqwest::init_client};
+
+    use super::*;
+
+    const FILENAME: &str = "[ANi] 模擬後宮體驗 - 01 [1080P][Baha][WEB-DL][AAC AVC][CHT].mp4";
+
+    fn setting(provider: LlmProvider, url: String) -> config::Llama {
+        config::Llama {
+            provider,
+            model: "model".into(),
+            url,
+            token: "token".into(),
+            timeout_secs: 5,
+            retries: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_decode() {
+        let settings = util::config::Settings::load_from_file("settings.json").unwrap();
+        let _ = init_client(settings.proxy).unwrap();
+        if let Some(llama) = settings.llama {
+            Llama::init(&llama).unwrap();
+        }
+
+        let content = Llama::get().unwrap().decode(FILENAME).await.unwrap();
+        dbg!(content);
+    }
+
+    #[tokio::test]
+    async fn test_openai() {
+        let _ = init_client(None);
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .and(body_partial_json(serde_json::json!({
+                "response_format": { "type": "json_schema" }
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "choices": [{
+                    "index": 0,
+                    "message": {
+                        "role": "assistant",
+                        "content": "```json\n{\"title\": \"模擬後宮體驗\", \"season\": 1, \"episode\": 1}\n```"
+                    }
+                }]
+            })))
+            .mount(&server)
+            .await;
+
+        let url = format!("{}/v1/chat/completions", server.uri());
+        let llama = Llama::new(&setting(LlmProvider::OpenAI, url));
+        let content = llama.decode(FILENAME).await.unwrap();
+        assert_eq!(content.title, "模擬後宮體驗");
+        assert_eq!(content.episode, 1);
+    }
+
+    #[tokio::test]
+    async fn test_ollama() {
+        let _ = init_client(None);
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .and(body_partial_json(serde_json::json!({ "stream": false })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "message": {
+                    "role": "assistant",
+                    "content": "{\"title\": \"模擬後宮體驗\", \"season\": 1, \"episode\": 1}"
+                },
+                "done": true
+            })))
+            .mount(&server)
+            .await;
+
+        let llama = Llama::new(&setting(LlmProvider::Ollama, server.uri()));
+        let content = llama.decode(FILENAME).await.unwrap();
+        assert_eq!(content.season, 1);
+        assert_eq!(content.episode, 1);
+    }
+
+    #[tokio::test]
+    async fn test_llamacpp_retry() {
+        let _ = init_client(None);
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .and(body_partial_json(serde_json::json!({ "json_schema": schema() })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "choices": [{
+                    "index": 0,
+                    "message": {
+                        "role": "assistant",
+                        "content": "Result: {\"title\": \"模擬後宮體驗\", \"season\": 1, \"episode\": 1}"
+                    }
+                }]
+            })))
+            .mount(&server)
+            .await;
+
+        let llama = Llama::new(&setting(LlmProvider::LlamaCpp, server.uri()));
+        let content = llama.decode(FILENAME).await.unwrap();
+        assert_eq!(content.episode, 1);
+    }
+
+    #[test]
+    fn test_extract_json() {
+        assert!(extract_json("no json here").is_err());
+        let content =
+            extract_json("{\"a\": 1} then {\"title\": \"t\", \"season\": 2, \"episode\": 3}")
+                .unwrap();
+        assert_eq!(content.season, 2);
+    }
+}
@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+use snafu::ResultExt;
+
+use crate::util::reqwest::client;
+
+use super::{check_status, ChatCompletionMessage, Error, Provider, RequestSnafu};
+
+/// Ollama 原生接口，url 为服务地址，如 http://localhost:11434
+pub struct Ollama {
+    model: String,
+    url: String,
+}
+
+impl Ollama {
+    pub fn new(model: &str, url: &str) -> Self {
+        Self {
+            model: model.to_owned(),
+            url: url.trim_end_matches('/').to_owned(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for Ollama {
+    async fn chat(
+        &self,
+        messages: &[ChatCompletionMessage],
+        schema: &serde_json::Value,
+        timeout: Duration,
+    ) -> Result<String, Error> {
+        // format 传入 JSON schema 时 Ollama 会约束输出的结构
+        let chat_req = serde_json::json!({
+            "model": self.model,
+            "messages": messages,
+            "stream": false,
+            "format": schema,
+            "options": { "temperature": 0.01 }
+        });
+
+        let res = client()
+            .post(format!("{}/api/chat", self.url))
+            .timeout(timeout)
+            .json(&chat_req)
+            .send()
+            .await
+            .context(RequestSnafu)?;
+        let res = check_status(res)
+            .await?
+            .json::<Response>()
+            .await
+            .context(RequestSnafu)?;
+
+        Ok(res.message.content)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageResponse {
+    content: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    message: MessageResponse,
+}
@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+use snafu::ResultExt;
+
+use crate::util::reqwest::client;
+
+use super::{check_status, ChatCompletionMessage, Error, Provider, RequestSnafu};
+
+/// OpenAI 兼容的接口，url 为完整的 chat/completions 地址
+pub struct OpenAI {
+    model: String,
+    url: String,
+    token: String,
+}
+
+impl OpenAI {
+    pub fn new(model: &str, url: &str, token: &str) -> Self {
+        Self {
+            model: model.to_owned(),
+            url: url.to_owned(),
+            token: token.to_owned(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for OpenAI {
+    async fn chat(
+        &self,
+        messages: &[ChatCompletionMessage],
+        schema: &serde_json::Value,
+        timeout: Duration,
+    ) -> Result<String, Error> {
+        let chat_req = serde_json::json!({
+            "model": self.model,
+            "messages": messages,
+            "temperature": 0.01,
+            "response_format": {
+                "type": "json_schema",
+                "json_schema": {
+                    "name": "episode",
+                    "strict": true,
+                    "schema": schema
+                }
+            }
+        });
+
+        let res = client()
+            .post(&self.url)
+            .bearer_auth(&self.token)
+            .timeout(timeout)
+            .json(&chat_req)
+            .send()
+            .await
+            .context(RequestSnafu)?;
+        let res = check_status(res)
+            .await?
+            .json::<Response>()
+            .await
+            .context(RequestSnafu)?;
+
+        let choice = res.choices.into_iter().next().ok_or(Error::NoChoice)?;
+        Ok(choice.message.content)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct MessageResponse {
+    pub content: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct ChoiceResponse {
+    pub message: MessageResponse,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct Response {
+    pub choices: Vec<ChoiceResponse>,
+}
@@ -1,35 +1,37 @@
+pub mod bangumi;
 pub mod config;
 pub mod llama;
+pub mod parser;
 pub mod reqwest;
+pub mod title;
 
-use std::{collections::HashMap, path::PathBuf};
+use std::collections::HashMap;
 
 use snafu::ResultExt;
 
 use tracing::{info, warn};
-use upload_backend::Backend;
 
-use crate::store;
+use crate::{store, target};
 
 pub async fn convert_storage(
     storage: Vec<config::Storage>,
-) -> Result<HashMap<String, Box<dyn Backend + Send + Sync>>, Error> {
+) -> Result<HashMap<String, Box<dyn target::Target>>, Error> {
     let db = store::Db::get_onedrive().context(DbSnafu)?;
+    let sessions = store::Db::get_session().context(DbSnafu)?;
 
-    let mut backends: HashMap<String, Box<dyn Backend + Send + Sync>> = HashMap::new();
+    let mut backends: HashMap<String, Box<dyn target::Target>> = HashMap::new();
     for (i, s) in storage.into_iter().enumerate() {
         match s {
-            config::Storage::Local { root } => {
+            config::Storage::Local { name, root, mode } => {
                 info! {"Loading Local: {:?}", root};
                 tokio::fs::create_dir_all(&root).await.context(IoSnafu)?;
-                backends.insert(
-                    format!("local{}", i),
-                    Box::new(upload_backend::backend::Local::new(root)),
-                );
+                let name = name.unwrap_or_else(|| format!("local{}", i));
+                let local = target::Local::new(&name, root, mode, sessions.clone());
+                backends.insert(name, Box::new(local));
             }
             config::Storage::Webdav { name, url, auth } => {
                 info! {"Loading Webdav: {}", name};
-                let webdav = upload_backend::backend::Webdav::new(auth.0, &url).await;
+                let webdav = target::Webdav::new(&name, auth.0, &url, sessions.clone()).await;
                 if webdav.is_err() {
                     warn!("Error loading {} Webdav", name);
                     continue;
@@ -44,83 +46,63 @@ pub async fn convert_storage(
                 api_type,
             } => {
                 info! {"Loading Onedrive: {}", name};
-                let refresh_token = db.get_refresh_token(name.clone()).context(DbSnafu)?;
-                let onedrive = login_onedrive(
+                // 启动时不再交互登录，没有 refresh token 的目标暂停上传
+                if db
+                    .get_refresh_token(name.clone())
+                    .context(DbSnafu)?
+                    .is_none()
+                {
+                    warn!(
+                        "Onedrive {} is not authorized, run `mikan-subscriber onedrive-login {}`",
+                        name, name
+                    );
+                }
+                let onedrive = target::Onedrive::new(
+                    &name,
                     &client_id,
                     &client_secret,
-                    &api_type,
-                    &root,
-                    refresh_token.as_deref(),
-                )
-                .await;
-
-                if let Err(e) = &onedrive {
-                    warn!("Error loading {} Onedrive: {}", name, e);
-                    continue;
-                }
-                let onedrive = onedrive.unwrap();
-
-                db.insert_refresh_token(onedrive.refresh_token(), name.clone())
-                    .context(DbSnafu)?;
-
+                    api_type,
+                    root,
+                    db.clone(),
+                    sessions.clone(),
+                );
                 backends.insert(name, Box::new(onedrive));
             }
-        }
-    }
-
-    Ok(backends)
-}
-
-async fn login_onedrive(
-    client_id: &str,
-    client_secret: &str,
-    api_type: &upload_backend::backend::OnedriveApiType,
-    root: &PathBuf,
-    refresh_token: Option<&str>,
-) -> Result<upload_backend::backend::Onedrive, Error> {
-    match refresh_token {
-        Some(token) => {
-            let ret = upload_backend::backend::Onedrive::new_with_refresh_token(
-                client_id,
-                client_secret,
-                token,
-                api_type.clone(),
-                root,
-            )
-            .await;
-
-            match ret {
-                Ok(onedrive) => Ok(onedrive),
-                Err(e) => {
-                    warn!("Error loading Onedrive: {}", e);
-                    let onedrive = upload_backend::backend::Onedrive::new_with_code(
-                        client_id,
-                        client_secret,
-                        "http://localhost:20080".to_string(),
-                        api_type.clone(),
-                        root,
-                    )
-                    .await
-                    .map_err(|e| Error::Onedrive {
-                        error: e.to_string(),
-                    })?;
-
-                    Ok(onedrive)
+            config::Storage::S3(s3) => {
+                info! {"Loading S3: {}", s3.name};
+                let name = s3.name.clone();
+                match target::S3::new(s3, sessions.clone()) {
+                    Ok(s3) => {
+                        backends.insert(name, Box::new(s3));
+                    }
+                    Err(e) => warn!("Error loading {} S3: {}", name, e),
+                }
+            }
+            config::Storage::Sftp(sftp) => {
+                info! {"Loading Sftp: {}", sftp.name};
+                let name = sftp.name.clone();
+                backends.insert(name, Box::new(target::Sftp::new(sftp, sessions.clone())));
+            }
+            config::Storage::Rclone(rclone) => {
+                info! {"Loading Rclone: {}", rclone.name};
+                let name = rclone.name.clone();
+                let binary = rclone.binary.clone();
+                match target::Rclone::new(rclone).await {
+                    Ok(rclone) => {
+                        backends.insert(name, Box::new(rclone));
+                    }
+                    Err(e) => warn!(
+                        "Error loading {} Rclone, is {} installed? {}",
+                        name,
+                        binary.display(),
+                        e
+                    ),
                 }
             }
         }
-        None => upload_backend::backend::Onedrive::new_with_code(
-            client_id,
-            client_secret,
-            "http://localhost:20080".to_string(),
-            api_type.clone(),
-            root,
-        )
-        .await
-        .map_err(|e| Error::Onedrive {
-            error: e.to_string(),
-        }),
     }
+
+    Ok(backends)
 }
 
 #[derive(Debug, snafu::Snafu)]
@@ -128,9 +110,6 @@ pub enum Error {
     #[snafu(display("Error loading DB: {}", source))]
     Db { source: redb::Error },
 
-    #[snafu(display("Error loading Onedrive: {}", error))]
-    Onedrive { error: String },
-
     #[snafu(display("Error IO: {}", source))]
     Io { source: std::io::Error },
 }
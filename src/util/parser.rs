@@ -0,0 +1,194 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use super::{llama::ContentResponse, title};
+
+static ROMAN_SEASON: Lazy<Regex> = Lazy::new(|| Regex::new(r"\s(II|III|IV)(?:\s|$)").unwrap());
+// 紧跟在中日文标题后的数字表示季数，如 我内心危险的东西2
+static CJK_SEASON: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"([\p{Han}\p{Hiragana}\p{Katakana}])(\d{1,2})$").unwrap());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confidence {
+    Low,
+    High,
+}
+
+/// 根据常见的字幕组命名规则解析文件名，无法识别集数时返回 None
+pub fn parse(filename: &str) -> Option<(ContentResponse, Confidence)> {
+    let name = strip_extension(filename);
+    let (episode, range) = title::episode_match(name)?;
+
+    // 字幕组之后、集数之前的部分为标题
+    let start = title::subgroup_match(name).map(|(_, r)| r.end).unwrap_or(0);
+    let start = start.min(range.start);
+    let mut raw = clean(&name[start..range.start]);
+
+    let mut season = None;
+    if let Some((s, r)) = title::season_match(&raw) {
+        season = Some(s);
+        raw = clean(&format!("{} {}", &raw[..r.start], &raw[r.end..]));
+    } else if let Some(caps) = CJK_SEASON.captures(&raw) {
+        season = caps[2].parse().ok();
+        raw = raw[..caps.get(2).unwrap().start()].to_owned();
+    } else if let Some(caps) = ROMAN_SEASON.captures(&raw) {
+        // 罗马数字通常是正式标题的一部分，保留在标题中
+        season = match &caps[1] {
+            "II" => Some(2),
+            "III" => Some(3),
+            "IV" => Some(4),
+            _ => None,
+        };
+    }
+
+    let title = prefer_alphabetic(&raw);
+    if title.is_empty() {
+        return None;
+    }
+
+    // 没有字幕组或者标题中残留括号时，说明文件名不是常见的格式
+    let confidence = if title::subgroup(name).is_some() && !title.contains(['[', ']', '【', '】'])
+    {
+        Confidence::High
+    } else {
+        Confidence::Low
+    };
+
+    Some((
+        ContentResponse {
+            title,
+            season: season.unwrap_or(1),
+            episode,
+        },
+        confidence,
+    ))
+}
+
+fn strip_extension(filename: &str) -> &str {
+    match filename.rsplit_once('.') {
+        Some((name, ext)) if ext.len() <= 4 && ext.chars().all(|c| c.is_ascii_alphanumeric()) => {
+            name
+        }
+        _ => filename,
+    }
+}
+
+// 去掉首尾的分隔符并合并空白
+fn clean(s: &str) -> String {
+    s.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_matches(|c: char| c.is_whitespace() || "-_[]【】()".contains(c))
+        .to_owned()
+}
+
+// 同时包含中文名和罗马音时，优先使用罗马音
+fn prefer_alphabetic(title: &str) -> String {
+    let is_alphabetic = |s: &str| s.chars().any(|c| c.is_ascii_alphabetic()) && !has_cjk(s);
+
+    if let Some(part) = title.split(" / ").map(str::trim).find(|s| is_alphabetic(s)) {
+        return part.to_owned();
+    }
+
+    if has_cjk(title) {
+        if let Some(pos) = title.find(|c: char| c.is_ascii_alphabetic()) {
+            let rest = &title[pos..];
+            if is_alphabetic(rest) && title[..pos].ends_with(' ') {
+                return rest.to_owned();
+            }
+        }
+    }
+
+    title.to_owned()
+}
+
+fn has_cjk(s: &str) -> bool {
+    s.chars().any(|c| {
+        matches!(c, '\u{3040}'..='\u{30ff}' | '\u{3400}'..='\u{4dbf}' | '\u{4e00}'..='\u{9fff}')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        // 与 llama.rs 中的示例保持一致
+        let cases = [
+            (
+                "[Billion Meta Lab] 恋语轻唱 Sasayaku You ni Koi wo Utau [02][1080][CHS].mp4",
+                "Sasayaku You ni Koi wo Utau",
+                1,
+                2,
+            ),
+            (
+                "[Up to 21°C] 永生 第三季 - 06 (B-Global Donghua 1920x1080 HEVC AAC MKV) [9F7CAB79].mkv",
+                "永生",
+                3,
+                6,
+            ),
+            (
+                "[GJ.Y] 我内心危险的东西2 - 05 (B-Global 1920x1080 HEVC AAC MKV) [60D8C635].mkv",
+                "我内心危险的东西",
+                2,
+                5,
+            ),
+            (
+                "[Up to 21°C] Shinigami Bocchan to Kuro Maid 3rd Season - 30 (CR 1920x1080 AVC AAC MKV) [CE516DAA].mkv",
+                "Shinigami Bocchan to Kuro Maid",
+                3,
+                30,
+            ),
+            (
+                "[Sakurato] Dungeon Meshi [17v2][AVC-8bit 1080p AAC][CHT].mp4",
+                "Dungeon Meshi",
+                1,
+                17,
+            ),
+            (
+                "[Up to 21°C] Mushoku Tensei II -  Isekai Ittara Honki Dasu Part 2 - 18 (ABEMA 1920x1080 AVC AAC MP4) [05628308].mp4",
+                "Mushoku Tensei II - Isekai Ittara Honki Dasu Part 2",
+                2,
+                18,
+            ),
+            (
+                "[ANi] 模擬後宮體驗 - 01 [1080P][Baha][WEB-DL][AAC AVC][CHT].mp4",
+                "模擬後宮體驗",
+                1,
+                1,
+            ),
+            (
+                "[LoliHouse] 迷宫饭 / Dungeon Meshi - 17 [WebRip 1080p HEVC-10bit AAC][简繁内封字幕].mkv",
+                "Dungeon Meshi",
+                1,
+                17,
+            ),
+            (
+                "[Nekomoe kissaten] Oshi no Ko S2 [05][1080p][CHS].mp4",
+                "Oshi no Ko",
+                2,
+                5,
+            ),
+            (
+                "【悠哈璃羽字幕社】[Dungeon Meshi][17][1080p][CHS].mp4",
+                "Dungeon Meshi",
+                1,
+                17,
+            ),
+        ];
+
+        for (filename, title, season, episode) in cases {
+            let (content, confidence) = parse(filename).unwrap();
+            assert_eq!(content.title, title, "{}", filename);
+            assert_eq!(content.season, season, "{}", filename);
+            assert_eq!(content.episode, episode, "{}", filename);
+            assert_eq!(confidence, Confidence::High);
+        }
+    }
+
+    #[test]
+    fn test_parse_failed() {
+        assert!(parse("[Sakurato] Dungeon Meshi [1080p][CHT].mp4").is_none());
+    }
+}
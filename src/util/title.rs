@@ -0,0 +1,130 @@
+use std::ops::Range;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+static SUBGROUP: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\s*[\[【]([^\]】]+)[\]】]").unwrap());
+static RESOLUTION: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)(?:\d{3,4}[x×](\d{3,4})|\b(\d{3,4})p\b|\b(4k)\b)").unwrap());
+
+/// 标题开头方括号中的字幕组名称
+pub fn subgroup(title: &str) -> Option<&str> {
+    subgroup_match(title).map(|(subgroup, _)| subgroup)
+}
+
+/// 字幕组名称及其所在方括号的位置
+pub fn subgroup_match(title: &str) -> Option<(&str, Range<usize>)> {
+    let caps = SUBGROUP.captures(title)?;
+    Some((caps.get(1)?.as_str().trim(), caps.get(0).unwrap().range()))
+}
+
+/// 视频的垂直分辨率，如 1080p、1920x1080、4K
+pub fn resolution(title: &str) -> Option<u32> {
+    let caps = RESOLUTION.captures(title)?;
+    if caps.get(3).is_some() {
+        return Some(2160);
+    }
+
+    caps.get(1)
+        .or_else(|| caps.get(2))
+        .and_then(|m| m.as_str().parse().ok())
+}
+
+/// 标题是否包含关键字，忽略大小写
+pub fn contains_keyword(title: &str, keyword: &str) -> bool {
+    title.to_lowercase().contains(&keyword.to_lowercase())
+}
+
+static EPISODE: Lazy<Vec<Regex>> = Lazy::new(|| {
+    [
+        r"(?i)\bS\d{1,2}E(\d{1,4})(?:v\d+)?\b",
+        r"第(\d{1,4})[话話集]",
+        r"\s-\s+(\d{1,4})(?:v\d+)?(?:\s|$|\[|\(|【)",
+        r"[\[【](\d{1,4})(?:v\d+)?(?:\s*END)?[\]】]",
+        r"(?i)[\[\s]EP?(\d{1,4})(?:v\d+)?[\]\s]",
+    ]
+    .iter()
+    .map(|r| Regex::new(r).unwrap())
+    .collect()
+});
+static SEASON: Lazy<Vec<Regex>> = Lazy::new(|| {
+    [
+        r"(?i)\bS(\d{1,2})(?:E\d{1,4})?\b",
+        r"(?i)\b(\d{1,2})(?:st|nd|rd|th) Season\b",
+        r"(?i)\bSeason (\d{1,2})\b",
+        r"第([一二三四五六七八九十\d]{1,3})[季期]",
+    ]
+    .iter()
+    .map(|r| Regex::new(r).unwrap())
+    .collect()
+});
+static CODEC: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)\b(HEVC|x265|H\.?265|AVC|x264|H\.?264|AV1)\b").unwrap());
+
+// 常见的分辨率，避免将 [1080] 之类的标记当成集数
+const RESOLUTIONS: [u32; 6] = [480, 540, 576, 720, 1080, 2160];
+
+/// 标题中的集数
+pub fn episode(title: &str) -> Option<u32> {
+    episode_match(title).map(|(episode, _)| episode)
+}
+
+/// 标题中的集数及其标记所在的位置
+pub fn episode_match(title: &str) -> Option<(u32, Range<usize>)> {
+    EPISODE.iter().find_map(|r| {
+        r.captures_iter(title).find_map(|c| {
+            let episode = c.get(1)?.as_str().parse().ok()?;
+            (!RESOLUTIONS.contains(&episode)).then(|| (episode, c.get(0).unwrap().range()))
+        })
+    })
+}
+
+/// 标题中的季数，没有明确标记时为 None
+pub fn season(title: &str) -> Option<u32> {
+    season_match(title).map(|(season, _)| season)
+}
+
+/// 标题中的季数及其标记所在的位置
+pub fn season_match(title: &str) -> Option<(u32, Range<usize>)> {
+    SEASON.iter().find_map(|r| {
+        let caps = r.captures(title)?;
+        let season = caps.get(1)?.as_str();
+        let season = season.parse().ok().or_else(|| chinese_number(season))?;
+        Some((season, caps.get(0).unwrap().range()))
+    })
+}
+
+/// 视频编码，统一为 HEVC、AVC、AV1
+pub fn codec(title: &str) -> Option<&'static str> {
+    let codec = CODEC.captures(title)?.get(1)?.as_str().to_uppercase();
+    match codec.as_str() {
+        "HEVC" | "X265" | "H265" | "H.265" => Some("HEVC"),
+        "AVC" | "X264" | "H264" | "H.264" => Some("AVC"),
+        "AV1" => Some("AV1"),
+        _ => None,
+    }
+}
+
+/// 解析不超过九十九的中文数字
+pub fn chinese_number(s: &str) -> Option<u32> {
+    let digit = |c: char| "零一二三四五六七八九".chars().position(|d| d == c);
+    let chars = s.chars().collect::<Vec<_>>();
+    match chars.as_slice() {
+        ['十'] => Some(10),
+        ['十', b] => Some(10 + digit(*b)? as u32),
+        [a, '十'] => Some(digit(*a)? as u32 * 10),
+        [a, '十', b] => Some(digit(*a)? as u32 * 10 + digit(*b)? as u32),
+        [a] => Some(digit(*a)? as u32),
+        _ => None,
+    }
+}
+
+static REVISION: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)\b\d{1,4}v(\d)\b").unwrap());
+
+/// 修订版本号，如 [17v2] 为 2，没有标记时为 1
+pub fn revision(title: &str) -> u32 {
+    REVISION
+        .captures(title)
+        .and_then(|c| c.get(1)?.as_str().parse().ok())
+        .unwrap_or(1)
+}
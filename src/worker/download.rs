@@ -1,20 +1,38 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use librqbit::dht::Id20;
 use snafu::{ResultExt, Snafu};
 use tokio::select;
 use tracing::debug;
 
+use super::upload;
 use crate::{
-    bt,
+    bt, media, release,
     store::{self, DownloadTask},
     subscribe::Subscription,
-    util::config::Download,
+    util::config::{Download, Files, Preference},
 };
 
-async fn download_handle(setting: Download) -> Result<DownloadHandle, Error> {
-    let seed_seconds = (setting.seed_hours * 3600.0) as u64;
-    let max_download_seconds = (setting.max_download_hours * 3600.0) as u64;
+async fn download_handle(
+    setting: Download,
+    files: Files,
+    preference: Arc<Preference>,
+) -> Result<DownloadHandle, Error> {
+    let seed = SeedPolicy::new(&setting);
+    let max_download = setting
+        .max_download_hours
+        .map(|h| Duration::from_secs_f32(h * 3600.0));
+    let stall_window = Duration::from_secs(setting.stall_minutes * 60);
+    let retry = Retry {
+        max_attempts: setting.max_attempts,
+        base: setting.retry_base_secs,
+        max: setting.retry_max_secs,
+    };
 
     let thread_num = setting.threads;
 
@@ -24,6 +42,7 @@ async fn download_handle(setting: Download) -> Result<DownloadHandle, Error> {
     let db = store::Db::get_download().context(DbSnafu)?;
 
     let (tx, rx) = flume::unbounded();
+    let files = Arc::new(files);
 
     // Start download threads
     for _ in 0..thread_num {
@@ -31,29 +50,39 @@ async fn download_handle(setting: Download) -> Result<DownloadHandle, Error> {
         let session_clone = session.clone();
         let db_clone = db.clone();
         let download_dir = download_dir.clone();
+        let files = files.clone();
 
         let handle = tokio::spawn(async move {
             loop {
                 // receive subscription
                 let (name, sub): (String, Subscription) = rx_clone.recv_async().await.unwrap();
                 let magnet = sub.magnet;
+                // 排队期间被修订版本取消的任务
+                if matches!(db_clone.get(name.clone()), Ok(None)) {
+                    tracing::info!("Skipping cancelled: {}", name);
+                    continue;
+                }
 
                 tracing::info!("Downloading: {}", name);
+                let deadline = max_download.map(|d| Instant::now() + d);
+                // 获取元数据期间没有进度可以参考，超过停滞时间仍未完成视为停滞
+                let metadata_timeout = max_download.map_or(stall_window, |d| d.min(stall_window));
                 // Add torrent
                 let ret = select! {
-                    _ = tokio::time::sleep(tokio::time::Duration::from_secs(max_download_seconds)) => {
+                    _ = tokio::time::sleep(metadata_timeout) => {
                         tracing::error!("Download timeout: {}", name);
-                        // set to blocked
-                        db_clone.update_state(name.clone(), store::DownloadTaskState::Blocked).unwrap_or_else(|e| {
-                            tracing::error!("Error updating state: {}", e);
-                        });
+                        block(&name, "Timeout fetching metadata".to_owned(), retry);
 
                         continue;
                     }
-                    ret = session_clone.add_torrent(&magnet)=> {
+                    ret = async {
+                        let only_files = select_files(&session_clone, &name, &magnet, &files).await?;
+                        session_clone.add_torrent(&magnet, only_files).await
+                    } => {
 
                         if let Err(e) = &ret {
                             tracing::error!("Error downloading: {}", e);
+                            block(&name, e.to_string(), retry);
                             continue;
                         }
 
@@ -73,33 +102,58 @@ async fn download_handle(setting: Download) -> Result<DownloadHandle, Error> {
                 }
 
                 // Wait for download to complete
-                // If download takes too long, delete the torrent and download record
-                select! {
-                    _ = tokio::time::sleep(tokio::time::Duration::from_secs(max_download_seconds)) => {
-                        tracing::error!("Download timeout: {}", name);
-                        session_clone.delete_torrent_by_id(id).await.unwrap_or_else(|e| {
-                            tracing::error!("Error deleting torrent: {}", e);
-                        });
-                        db_clone.update_state(name.clone(), store::DownloadTaskState::Blocked).unwrap_or_else(|e| {
-                            tracing::error!("Error updating state: {}", e);
-                        });
-
-                        continue;
-                    }
-                    ret = handle.wait_until_completed() => {
-                        if let Err(e) = &ret {
-                            tracing::error!("Error downloading: {}", e);
-                            continue;
+                // If download stalls or exceeds the cap, delete the torrent and block the task
+                let mut stall = Stall::new(stall_window, Instant::now());
+                let mut check = tokio::time::interval(STALL_CHECK_INTERVAL);
+                let completed = handle.wait_until_completed();
+                tokio::pin!(completed);
+                let ret = loop {
+                    select! {
+                        ret = &mut completed => break ret.map_err(|e| e.to_string()),
+                        _ = check.tick() => {
+                            let now = Instant::now();
+                            let stats = handle.stats();
+                            let peers = stats
+                                .live
+                                .as_ref()
+                                .map_or(0, |l| l.snapshot.peer_stats.live);
+                            if stall.update(stats.progress_bytes, peers, now) {
+                                break Err("Download stalled".to_owned());
+                            }
+                            if deadline.is_some_and(|d| now >= d) {
+                                break Err("Download timeout".to_owned());
+                            }
+                            if matches!(db_clone.get(name.clone()), Ok(None)) {
+                                break Err("Download cancelled".to_owned());
+                            }
                         }
+                    }
+                };
 
-                        if seed_seconds == 0 {
-                            session_clone.pause_torrent_by_handle(&handle).await.unwrap_or_else(|e| {
-                                tracing::error!("Error pausing: {}", e);
+                match ret {
+                    Err(e) => {
+                        tracing::error!("Error downloading {}: {}", name, e);
+                        session_clone
+                            .delete_torrent_by_id(id)
+                            .await
+                            .unwrap_or_else(|e| {
+                                tracing::error!("Error deleting torrent: {}", e);
                             });
-                        }
+                        block(&name, e, retry);
 
+                        continue;
                     }
-                };
+                    Ok(()) => {
+                        if !seed.seeds() {
+                            session_clone
+                                .pause_torrent_by_handle(&handle)
+                                .await
+                                .unwrap_or_else(|e| {
+                                    tracing::error!("Error pausing: {}", e);
+                                });
+                        }
+                    }
+                }
 
                 // download file or folder
                 let file_name = handle.name().unwrap_or_else(|| {
@@ -131,46 +185,81 @@ async fn download_handle(setting: Download) -> Result<DownloadHandle, Error> {
     Ok(DownloadHandle {
         _threads: threads,
         tx,
-        seed_seconds,
+        seed,
         session,
+        download_dir,
+        retry,
+        preference,
     })
 }
 
 pub struct DownloadHandle {
     _threads: Vec<tokio::task::JoinHandle<()>>,
     tx: flume::Sender<(String, Subscription)>,
-    seed_seconds: u64,
+    seed: SeedPolicy,
     session: bt::SessionGuard,
+    download_dir: PathBuf,
+    retry: Retry,
+    preference: Arc<Preference>,
 }
 
 impl DownloadHandle {
-    pub async fn add(&self, name: String, sub: Subscription) -> Result<(), Error> {
-        let bangume_id = sub.anime.bangumi_tv_id;
-        let db = store::Db::get_download().context(DbSnafu)?;
-        db.insert(
-            name.clone(),
-            DownloadTask {
-                url: sub.magnet.clone(),
-                anime_title: sub.anime.name.clone(),
-                air_date: sub.anime.air_date,
-                weekday: sub.anime.weekday.clone(),
-                state: store::DownloadTaskState::Pending,
-                bangumi_id: bangume_id,
-                added_at: chrono::Utc::now().timestamp() as u64,
-            },
-        )
-        .context(DbSnafu)?;
-        self.tx
-            .send_async((name, sub))
+    pub async fn add(&self, name: String, sub: Subscription, feed: String) -> Result<(), Error> {
+        self.queue(name, new_task(sub, feed, None)).await
+    }
+
+    // 添加修订版本，上传后替换旧版本，旧版本还没有开始上传时直接取消
+    pub async fn add_revision(
+        &self,
+        name: String,
+        sub: Subscription,
+        feed: String,
+        supersedes: String,
+    ) -> Result<(), Error> {
+        self.cancel(&supersedes).await?;
+        self.queue(name, new_task(sub, feed, Some(supersedes)))
             .await
-            .map_err(|e| Error::Send {
-                message: e.to_string(),
-            })?;
+    }
+
+    // 删除任务记录，正在下载的由下载线程在检查进度时删除，已经下载完成的标记为取消，
+    // 不再上传，由 delete_finished 删除种子和文件
+    async fn cancel(&self, name: &str) -> Result<(), Error> {
+        let db = store::Db::get_download().context(DbSnafu)?;
+        let Some(task) = db.get(name.to_owned()).context(DbSnafu)? else {
+            return Ok(());
+        };
 
+        match &task.state {
+            store::DownloadTaskState::Pending
+            | store::DownloadTaskState::Downloading
+            | store::DownloadTaskState::Blocked { .. } => {
+                tracing::info!("Cancelling superseded {}", name);
+                db.delete(name).context(DbSnafu)?;
+            }
+            // 上传线程可能正在读取文件，由 delete_finished 在上传结束后删除
+            store::DownloadTaskState::Downloaded {
+                file_path,
+                info_hash,
+            } => {
+                tracing::info!("Cancelling superseded {}", name);
+                db.update_state(
+                    name.to_owned(),
+                    store::DownloadTaskState::Cancelled {
+                        file_path: file_path.clone(),
+                        info_hash: info_hash.clone(),
+                    },
+                )
+                .context(DbSnafu)?;
+            }
+            _ => {}
+        }
         Ok(())
     }
 
-    async fn add_from_task(&self, name: String, task: DownloadTask) -> Result<(), Error> {
+    async fn queue(&self, name: String, task: DownloadTask) -> Result<(), Error> {
+        let db = store::Db::get_download().context(DbSnafu)?;
+        db.insert(name.clone(), task.clone()).context(DbSnafu)?;
+
         let sub = Subscription {
             magnet: task.url,
             anime: crate::subscribe::Anime {
@@ -181,13 +270,36 @@ impl DownloadHandle {
                 bangumi_tv_id: task.bangumi_id,
             },
         };
-        self.add(name, sub).await
+        self.tx
+            .send_async((name, sub))
+            .await
+            .map_err(|e| Error::Send {
+                message: e.to_string(),
+            })?;
+
+        Ok(())
+    }
+
+    async fn add_from_task(&self, name: String, task: DownloadTask) -> Result<(), Error> {
+        self.queue(
+            name,
+            DownloadTask {
+                state: store::DownloadTaskState::Pending,
+                ..task
+            },
+        )
+        .await
     }
 
     // Initialize download worker
-    pub async fn init(setting: Download) -> Result<Arc<Self>, Error> {
-        let handle = Arc::new(download_handle(setting).await?);
+    pub async fn init(
+        setting: Download,
+        files: Files,
+        preference: Arc<Preference>,
+    ) -> Result<Arc<Self>, Error> {
+        let handle = Arc::new(download_handle(setting, files, preference).await?);
         let db = store::Db::get_download().context(DbSnafu)?;
+        handle.reconcile().await?;
 
         let ret = db
             .get_with_state(|state| {
@@ -211,6 +323,9 @@ impl DownloadHandle {
                 handle_cloned.delete_finished().await.unwrap_or_else(|e| {
                     tracing::error!("Error deleting finished: {}", e);
                 });
+                handle_cloned.retry_blocked().await.unwrap_or_else(|e| {
+                    tracing::error!("Error retrying blocked: {}", e);
+                });
 
                 tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
             }
@@ -219,6 +334,202 @@ impl DownloadHandle {
         Ok(handle)
     }
 
+    // 将从保存的会话中恢复的种子与任务表对应：属于任务的种子继续使用，
+    // 没有对应任务的种子连同文件一起删除，需要做种但不在会话中的任务重新添加
+    async fn reconcile(&self) -> Result<(), Error> {
+        let db = store::Db::get_download().context(DbSnafu)?;
+        let tasks = db.get_all().context(DbSnafu)?;
+        let mut torrents = self
+            .session
+            .torrents()
+            .into_iter()
+            .map(|(id, handle)| (handle.info_hash().as_string(), (id, handle)))
+            .collect::<HashMap<_, _>>();
+        let now = chrono::Utc::now().timestamp() as u64;
+
+        // 无法确定 info hash 的任务可能对应任意种子，此时不清理
+        let mut unknown = false;
+        for (name, task) in tasks {
+            let Some(info_hash) = task_info_hash(&task) else {
+                unknown |= matches!(
+                    task.state,
+                    store::DownloadTaskState::Pending | store::DownloadTaskState::Downloading
+                );
+                continue;
+            };
+            let torrent = torrents.remove(&info_hash);
+
+            match (&task.state, torrent) {
+                // 超时后种子在会话中完成了下载，接管为已下载
+                (
+                    store::DownloadTaskState::Blocked { .. } | store::DownloadTaskState::GaveUp,
+                    Some((id, handle)),
+                ) => {
+                    if handle.stats().finished {
+                        tracing::info!("Adopting finished torrent: {}", name);
+                        let file_path = self.download_dir.join(handle.name().unwrap_or_default());
+                        db.update_state(
+                            name,
+                            store::DownloadTaskState::Downloaded {
+                                file_path,
+                                info_hash,
+                            },
+                        )
+                        .context(DbSnafu)?;
+                    } else {
+                        self.session
+                            .delete_torrent_by_id(id)
+                            .await
+                            .context(SessionSnafu)?;
+                    }
+                }
+                (
+                    store::DownloadTaskState::Downloaded { file_path, .. }
+                    | store::DownloadTaskState::Finished { file_path, .. }
+                    | store::DownloadTaskState::Partial { file_path, .. },
+                    None,
+                ) if file_path.exists() && self.seeding(&task.state, now) => {
+                    tracing::info!("Re-adding torrent for seeding: {}", name);
+                    let session = self.session.clone();
+                    // 重新获取元数据可能很慢，不阻塞启动
+                    let only_files = task.only_files.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = session.add_torrent(&task.url, only_files).await {
+                            tracing::warn!("Error re-adding {}: {}", name, e);
+                        }
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        if unknown {
+            return Ok(());
+        }
+        for (info_hash, (id, _)) in torrents {
+            tracing::warn!("Removing orphaned torrent: {}", info_hash);
+            self.session
+                .delete_torrent_by_id(id)
+                .await
+                .context(SessionSnafu)?;
+        }
+
+        Ok(())
+    }
+
+    // 下载完成的任务是否还需要做种，重新添加的种子从零开始统计分享率
+    fn seeding(&self, state: &store::DownloadTaskState, now: u64) -> bool {
+        let fresh = SeedStats {
+            ratio: 0.0,
+            seeders: None,
+        };
+        match state {
+            store::DownloadTaskState::Downloaded { .. } => self.seed.seeds(),
+            store::DownloadTaskState::Finished { finish_time, .. }
+            | store::DownloadTaskState::Partial { finish_time, .. } => !self
+                .seed
+                .should_stop(now.saturating_sub(*finish_time), Some(fresh)),
+            _ => false,
+        }
+    }
+
+    // 重新下载到了重试时间的任务，超过重试次数的改为下载同一集的其他版本
+    async fn retry_blocked(&self) -> Result<(), Error> {
+        let db = store::Db::get_download().context(DbSnafu)?;
+        let now = chrono::Utc::now().timestamp() as u64;
+        let due = db
+            .get_with_state(|state| {
+                matches!(state, store::DownloadTaskState::Blocked { retry_at } if retry_at <= now)
+            })
+            .context(DbSnafu)?;
+
+        for (name, task) in due {
+            if task.attempts < self.retry.max_attempts {
+                tracing::info!("Retrying {}, attempt {}", name, task.attempts + 1);
+                self.add_from_task(name, task).await?;
+                continue;
+            }
+
+            let error = task.last_error.clone().unwrap_or_default();
+            let alternate = self.alternate(&name, &task)?;
+            db.update_state(name.clone(), store::DownloadTaskState::GaveUp)
+                .context(DbSnafu)?;
+            store::Db::get_history()
+                .context(DbSnafu)?
+                .push(
+                    name.clone(),
+                    store::HistoryEvent::DownloadFailed {
+                        error: error.clone(),
+                        alternate: alternate.as_ref().map(|(n, _)| n.clone()),
+                    },
+                )
+                .unwrap_or_else(|e| {
+                    tracing::error!("Error updating history: {}", e);
+                });
+
+            match alternate {
+                Some((alternate, magnet)) => {
+                    tracing::warn!("Giving up {}, downloading {} instead", name, alternate);
+                    self.queue(
+                        alternate,
+                        DownloadTask {
+                            url: magnet,
+                            state: store::DownloadTaskState::Pending,
+                            added_at: now,
+                            uploads: HashMap::new(),
+                            only_files: None,
+                            attempts: 0,
+                            last_error: None,
+                            ..task
+                        },
+                    )
+                    .await?;
+                }
+                None => tracing::error!("Giving up downloading {}: {}", name, error),
+            }
+        }
+
+        Ok(())
+    }
+
+    // 同一集中还没有尝试过的其他版本，按偏好选出后记为该集选中的版本
+    fn alternate(
+        &self,
+        name: &str,
+        task: &DownloadTask,
+    ) -> Result<Option<(String, String)>, Error> {
+        // 修订版本下载失败时保留已经上传的旧版本
+        if task.supersedes.is_some() {
+            return Ok(None);
+        }
+        let Some(key) = release::key(name, task.bangumi_id) else {
+            return Ok(None);
+        };
+        let releases = store::Db::get_release().context(DbSnafu)?;
+        let Some(mut episode) = releases.get(key.clone()).context(DbSnafu)? else {
+            return Ok(None);
+        };
+
+        let tasks = store::Db::get_download().context(DbSnafu)?;
+        let mut untried = Vec::new();
+        for candidate in &episode.candidates {
+            if candidate != name
+                && episode.magnets.contains_key(candidate)
+                && tasks.get(candidate.clone()).context(DbSnafu)?.is_none()
+            {
+                untried.push(candidate.as_str());
+            }
+        }
+        let Some(chosen) = release::select(&self.preference, untried).map(str::to_owned) else {
+            return Ok(None);
+        };
+
+        let magnet = episode.magnets[&chosen].clone();
+        episode.chosen = chosen.clone();
+        releases.insert(key, episode).context(DbSnafu)?;
+        Ok(Some((chosen, magnet)))
+    }
+
     // Delete download record
     async fn delete_download(&self, info_hash: Id20) -> Result<(), Error> {
         self.session
@@ -228,60 +539,346 @@ impl DownloadHandle {
         Ok(())
     }
 
-    // Delete download records that have been finished for a certain amount of time
+    // 按做种策略删除已经上传的任务，超出磁盘预算时先删除最早完成的
     async fn delete_finished(&self) -> Result<(), Error> {
         let db = store::Db::get_download().context(DbSnafu)?;
+        let cancelled = db
+            .get_with_state(|state| matches!(state, store::DownloadTaskState::Cancelled { .. }))
+            .context(DbSnafu)?;
+        for (name, task) in cancelled {
+            if upload::is_uploading(&name) {
+                continue;
+            }
+            if let store::DownloadTaskState::Cancelled {
+                file_path,
+                info_hash,
+            } = task.state
+            {
+                self.remove_finished(&name, &info_hash, &file_path).await;
+            }
+        }
+
         let ret = db
-            .get_with_state(|state| matches!(state, store::DownloadTaskState::Finished { .. }))
+            .get_with_state(|state| {
+                matches!(
+                    state,
+                    store::DownloadTaskState::Finished { .. }
+                        | store::DownloadTaskState::Partial { .. }
+                )
+            })
             .context(DbSnafu)?;
+        let now = chrono::Utc::now().timestamp() as u64;
 
+        let mut seeding = Vec::new();
         for (name, task) in ret {
-            match task.state {
+            // 只比较了大小的上传同样按做种策略和磁盘预算删除，
+            // 但部分上传的任务中没有任何目标校验通过时本地文件是唯一可信的副本，不能删除
+            let verified = task
+                .uploads
+                .values()
+                .any(|u| matches!(u, store::UploadState::Uploaded));
+            if matches!(task.state, store::DownloadTaskState::Partial { .. }) && !verified {
+                continue;
+            }
+
+            let (finish_time, info_hash, file_path) = match task.state {
                 store::DownloadTaskState::Finished {
                     finish_time,
                     info_hash,
                     file_path,
-                } => {
-                    if finish_time + self.seed_seconds < chrono::Utc::now().timestamp() as u64 {
-                        let ret = self.delete_download(info_hash.parse().unwrap()).await;
-                        if let Err(e) = ret {
-                            tracing::warn!(
-                                "Error deleting download: {},try to directly rm file",
-                                e
-                            );
-
-                            if file_path.exists() {
-                                if file_path.is_file() {
-                                    if std::fs::remove_file(&file_path).is_err() {
-                                        tracing::error!(
-                                            "Error deleting file {}: {}",
-                                            file_path.display(),
-                                            e
-                                        );
-                                    }
-                                } else {
-                                    std::fs::remove_dir_all(&file_path).unwrap_or_else(|e| {
-                                        tracing::error!(
-                                            "Error deleting folder {}: {}",
-                                            file_path.display(),
-                                            e
-                                        );
-                                    });
-                                }
-                            }
-                        }
-
-                        db.delete(&name).unwrap_or_else(|e| {
-                            tracing::error!("Error deleting download in db: {}: {}", name, e);
-                        });
-                    }
                 }
+                | store::DownloadTaskState::Partial {
+                    finish_time,
+                    info_hash,
+                    file_path,
+                    ..
+                } => (finish_time, info_hash, file_path),
                 _ => unreachable!(),
+            };
+
+            let elapsed = now.saturating_sub(finish_time);
+            let mut stats = self
+                .session
+                .stats(info_hash.parse().unwrap())
+                .map(|stats| SeedStats {
+                    ratio: stats.uploaded_bytes as f64 / stats.total_bytes.max(1) as f64,
+                    seeders: None,
+                });
+            if let Some(stats) = &mut stats {
+                if self.seed.seeders.is_some() && elapsed >= self.seed.min {
+                    // rqbit 只知道连接上的 peer，整个 swarm 的做种者数量向 tracker 查询，
+                    // 其中包含自己
+                    stats.seeders = bt::scrape::seeders(&task.url, &info_hash)
+                        .await
+                        .map(|n| (n as usize).saturating_sub(1));
+                }
+            }
+            if self.seed.should_stop(elapsed, stats) {
+                self.remove_finished(&name, &info_hash, &file_path).await;
+            } else {
+                seeding.push((finish_time, name, info_hash, file_path));
+            }
+        }
+
+        let Some(budget) = self.seed.disk_budget else {
+            return Ok(());
+        };
+        seeding.sort_by_key(|(finish_time, ..)| *finish_time);
+        let mut sizes = Vec::with_capacity(seeding.len());
+        for (_, _, _, file_path) in &seeding {
+            sizes.push(disk_usage(file_path).await);
+        }
+        let mut total = sizes.iter().sum::<u64>();
+        for ((_, name, info_hash, file_path), size) in seeding.into_iter().zip(sizes) {
+            if total <= budget {
+                break;
             }
+            tracing::info!("Seeding disk budget exceeded, removing {}", name);
+            self.remove_finished(&name, &info_hash, &file_path).await;
+            total -= size;
         }
 
         Ok(())
     }
+
+    // 删除种子和下载的文件，以及任务记录
+    async fn remove_finished(&self, name: &str, info_hash: &str, file_path: &Path) {
+        let ret = self.delete_download(info_hash.parse().unwrap()).await;
+        if let Err(e) = ret {
+            tracing::warn!("Error deleting download: {},try to directly rm file", e);
+
+            if file_path.exists() {
+                if file_path.is_file() {
+                    if std::fs::remove_file(file_path).is_err() {
+                        tracing::error!("Error deleting file {}: {}", file_path.display(), e);
+                    }
+                } else {
+                    std::fs::remove_dir_all(file_path).unwrap_or_else(|e| {
+                        tracing::error!("Error deleting folder {}: {}", file_path.display(), e);
+                    });
+                }
+            }
+        }
+
+        store::Db::get_download()
+            .and_then(|db| db.delete(name))
+            .unwrap_or_else(|e| {
+                tracing::error!("Error deleting download in db: {}: {}", name, e);
+            });
+    }
+}
+
+// 文件实际占用的空间，不计算移动到本地存储后留下的符号链接指向的文件
+async fn disk_usage(path: &Path) -> u64 {
+    let mut total = 0;
+    for file in media::scan(path).await {
+        if let Ok(metadata) = tokio::fs::symlink_metadata(&file).await {
+            if metadata.is_file() {
+                total += metadata.len();
+            }
+        }
+    }
+    total
+}
+
+/// 做种时的分享率和其他做种者数量，tracker 查询失败时做种者数量为 None
+#[derive(Debug, Clone, Copy)]
+struct SeedStats {
+    ratio: f64,
+    seeders: Option<usize>,
+}
+
+/// 做种策略：至少做种 min 秒，之后达到分享率、做种者数量或最长时间中的任一条件即停止
+#[derive(Debug, Clone, Copy)]
+struct SeedPolicy {
+    min: u64,
+    max: Option<u64>,
+    ratio: Option<f64>,
+    seeders: Option<usize>,
+    disk_budget: Option<u64>,
+}
+
+impl SeedPolicy {
+    fn new(download: &Download) -> Self {
+        let seeding = &download.seeding;
+        Self {
+            min: (download.seed_hours * 3600.0) as u64,
+            max: seeding.max_hours.map(|h| (h * 3600.0) as u64),
+            ratio: seeding.ratio.map(f64::from),
+            seeders: seeding.min_seeders,
+            disk_budget: seeding
+                .disk_budget_gb
+                .map(|gb| (gb * 1024.0 * 1024.0 * 1024.0) as u64),
+        }
+    }
+
+    // 下载完成后是否需要做种
+    fn seeds(&self) -> bool {
+        self.min > 0 || self.ratio.is_some() || self.seeders.is_some()
+    }
+
+    // 种子不在会话中时 stats 为 None，只按时间判断
+    fn should_stop(&self, elapsed: u64, stats: Option<SeedStats>) -> bool {
+        if elapsed < self.min {
+            return false;
+        }
+        if self.max.is_some_and(|max| elapsed >= max) {
+            return true;
+        }
+        if self.ratio.is_none() && self.seeders.is_none() {
+            return true;
+        }
+        let Some(stats) = stats else {
+            return true;
+        };
+        self.ratio.is_some_and(|ratio| stats.ratio >= ratio)
+            || self
+                .seeders
+                .is_some_and(|seeders| stats.seeders.is_some_and(|n| n >= seeders))
+    }
+}
+
+/// 下载失败后的重试策略
+#[derive(Debug, Clone, Copy)]
+struct Retry {
+    max_attempts: u32,
+    base: u64,
+    max: u64,
+}
+
+impl Retry {
+    // 指数退避，达到最大次数后立即交给调度器改为下载其他版本
+    fn retry_at(&self, attempts: u32, now: u64) -> u64 {
+        if attempts >= self.max_attempts {
+            return now;
+        }
+        now + super::backoff(self.base, self.max, attempts)
+    }
+}
+
+// 记录失败次数和原因，任务进入 Blocked 等待重试
+fn block(name: &str, error: String, retry: Retry) {
+    let now = chrono::Utc::now().timestamp() as u64;
+    let ret = store::Db::get_download().and_then(|db| {
+        db.fail(name.to_owned(), error, |attempts| {
+            store::DownloadTaskState::Blocked {
+                retry_at: retry.retry_at(attempts, now),
+            }
+        })
+    });
+    if let Err(e) = ret {
+        tracing::error!("Error updating state: {}", e);
+    }
+}
+
+// 检查下载进度的间隔
+const STALL_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 下载停滞检测：已下载字节数增加或连接的 peer 数增加都视为有进展
+struct Stall {
+    window: Duration,
+    bytes: u64,
+    peers: usize,
+    since: Instant,
+}
+
+impl Stall {
+    fn new(window: Duration, now: Instant) -> Self {
+        Self {
+            window,
+            bytes: 0,
+            peers: 0,
+            since: now,
+        }
+    }
+
+    // 记录最新的进度，返回是否已经停滞
+    fn update(&mut self, bytes: u64, peers: usize, now: Instant) -> bool {
+        if bytes > self.bytes || peers > self.peers {
+            self.since = now;
+        }
+        self.bytes = self.bytes.max(bytes);
+        self.peers = peers;
+        now.duration_since(self.since) >= self.window
+    }
+}
+
+// 先获取元数据，只下载需要上传的文件，选择结果保存在任务中以便重启后沿用
+async fn select_files(
+    session: &bt::SessionGuard,
+    name: &str,
+    magnet: &str,
+    files: &Files,
+) -> Result<Option<Vec<usize>>, bt::Error> {
+    let db = store::Db::get_download();
+    let task = db
+        .as_ref()
+        .ok()
+        .and_then(|db| db.get(name.to_owned()).ok().flatten());
+    if let Some(only_files) = task.and_then(|t| t.only_files) {
+        return Ok(Some(only_files));
+    }
+
+    // 已经在会话中的种子沿用之前的选择
+    let Some(list) = session.list_files(magnet).await? else {
+        return Ok(None);
+    };
+    let only_files = media::select(&list, files);
+    match &only_files {
+        Some(only_files) => debug!(
+            "Selected {} of {} files: {}",
+            only_files.len(),
+            list.len(),
+            name
+        ),
+        None => tracing::warn!("No video found in {}, downloading all files", name),
+    }
+
+    if let Ok(db) = db {
+        db.update_only_files(name.to_owned(), only_files.clone())
+            .unwrap_or_else(|e| {
+                tracing::error!("Error updating selected files: {}", e);
+            });
+    }
+    Ok(only_files)
+}
+
+// 任务对应的 info hash，下载完成前从磁力链接中解析
+fn task_info_hash(task: &DownloadTask) -> Option<String> {
+    match &task.state {
+        store::DownloadTaskState::Downloaded { info_hash, .. }
+        | store::DownloadTaskState::Cancelled { info_hash, .. }
+        | store::DownloadTaskState::Finished { info_hash, .. }
+        | store::DownloadTaskState::Partial { info_hash, .. } => Some(info_hash.to_lowercase()),
+        _ => magnet_info_hash(&task.url),
+    }
+}
+
+// 只支持十六进制的 btih，与 Id20::as_string 的格式相同
+fn magnet_info_hash(magnet: &str) -> Option<String> {
+    let url = url::Url::parse(magnet).ok()?;
+    url.query_pairs()
+        .filter(|(key, _)| key == "xt")
+        .find_map(|(_, value)| value.strip_prefix("urn:btih:").map(str::to_lowercase))
+        .filter(|hash| hash.len() == 40 && hash.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+fn new_task(sub: Subscription, feed: String, supersedes: Option<String>) -> DownloadTask {
+    DownloadTask {
+        url: sub.magnet,
+        anime_title: sub.anime.name,
+        air_date: sub.anime.air_date,
+        weekday: sub.anime.weekday,
+        state: store::DownloadTaskState::Pending,
+        bangumi_id: sub.anime.bangumi_tv_id,
+        added_at: chrono::Utc::now().timestamp() as u64,
+        feed,
+        supersedes,
+        uploads: HashMap::new(),
+        only_files: None,
+        attempts: 0,
+        last_error: None,
+    }
 }
 
 #[derive(Debug, Snafu)]
@@ -295,3 +892,93 @@ pub enum Error {
     #[snafu(display("Error sending subscription: {}", message))]
     Send { message: String },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stall() {
+        let start = Instant::now();
+        let minutes = |m| start + Duration::from_secs(m * 60);
+        let mut stall = Stall::new(Duration::from_secs(30 * 60), start);
+
+        // 慢速但持续的下载不会停滞
+        for m in 1..=120 {
+            assert!(!stall.update(m * 1024, 1, minutes(m)));
+        }
+        // peer 断开后重新连接也算进展
+        assert!(!stall.update(120 * 1024, 0, minutes(140)));
+        assert!(!stall.update(120 * 1024, 1, minutes(149)));
+        assert!(!stall.update(120 * 1024, 1, minutes(178)));
+        assert!(stall.update(120 * 1024, 1, minutes(179)));
+    }
+
+    #[test]
+    fn test_seed_policy() {
+        let hours = |h: u64| h * 3600;
+        let stats = |ratio, seeders| Some(SeedStats { ratio, seeders });
+        let policy = SeedPolicy {
+            min: hours(1),
+            max: Some(hours(72)),
+            ratio: Some(2.0),
+            seeders: Some(5),
+            disk_budget: None,
+        };
+
+        // 最短时间内不停止
+        assert!(!policy.should_stop(hours(0), stats(10.0, Some(100))));
+        // 热门种子有足够的做种者
+        assert!(policy.should_stop(hours(2), stats(0.1, Some(5))));
+        // 小字幕组的种子做种到分享率或最长时间
+        assert!(!policy.should_stop(hours(2), stats(1.0, Some(1))));
+        assert!(policy.should_stop(hours(2), stats(2.0, Some(1))));
+        assert!(policy.should_stop(hours(72), stats(1.0, Some(1))));
+        // tracker 查询失败时不按做种者数量停止
+        assert!(!policy.should_stop(hours(2), stats(1.0, None)));
+        // 种子已经不在会话中
+        assert!(policy.should_stop(hours(2), None));
+
+        // 只设置最短时间时与原来的 seed_hours 相同
+        let policy = SeedPolicy {
+            min: hours(1),
+            max: None,
+            ratio: None,
+            seeders: None,
+            disk_budget: None,
+        };
+        assert!(!policy.should_stop(hours(0), stats(0.0, None)));
+        assert!(policy.should_stop(hours(1), stats(0.0, None)));
+    }
+
+    #[test]
+    fn test_retry_at() {
+        let retry = Retry {
+            max_attempts: 4,
+            base: 1800,
+            max: 3600,
+        };
+        assert_eq!(retry.retry_at(1, 0), 1800);
+        assert_eq!(retry.retry_at(2, 0), 3600);
+        assert_eq!(retry.retry_at(3, 0), 3600);
+        // 用完次数后立即寻找其他版本
+        assert_eq!(retry.retry_at(4, 100), 100);
+    }
+
+    #[test]
+    fn test_magnet_info_hash() {
+        assert_eq!(
+            magnet_info_hash(
+                "magnet:?xt=urn:btih:5D9140ED25BE2CFF3B981566792B668AB6976F58&tr=http%3a%2f%2ft.nyaatracker.com%2fannounce"
+            )
+            .as_deref(),
+            Some("5d9140ed25be2cff3b981566792b668ab6976f58")
+        );
+        // base32 编码的 btih
+        assert_eq!(
+            magnet_info_hash("magnet:?xt=urn:btih:LWIUB3JFXYWP6PZYCVTHSK3GRK3JO32Y"),
+            None
+        );
+        assert_eq!(magnet_info_hash("https://mikanani.me/a.torrent"), None);
+    }
+}
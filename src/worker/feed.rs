@@ -0,0 +1,196 @@
+use std::{collections::HashMap, sync::Arc};
+
+use tracing::{debug, error, info};
+
+use crate::{
+    filter::Filter,
+    release, store,
+    subscribe::{get_feed, Subscription},
+    util::{
+        config::{Preference, Subscribe},
+        title,
+    },
+};
+
+use super::DownloadHandle;
+
+// 定时检查订阅源，将未处理过的剧集加入下载队列
+pub async fn poll_feed(
+    subscribe: Subscribe,
+    download_worker: Arc<DownloadHandle>,
+    filter: Arc<Filter>,
+    preference: Arc<Preference>,
+) {
+    let interval = std::time::Duration::from_secs(subscribe.interval_minutes.max(1) * 60);
+
+    loop {
+        info!("Checking feed {}", subscribe.name);
+        match get_feed(&subscribe.url).await {
+            Ok(feed) => {
+                process_feed(&subscribe, feed, &download_worker, &filter, &preference).await;
+            }
+            Err(e) => {
+                error!("Error getting feed {}: {}", subscribe.name, e);
+            }
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+async fn process_feed(
+    subscribe: &Subscribe,
+    feed: HashMap<String, Subscription>,
+    download_worker: &DownloadHandle,
+    filter: &Filter,
+    preference: &Preference,
+) {
+    let db = store::Db::get_subscribe().unwrap();
+    let rejected = store::Db::get_rejected().unwrap();
+    let releases = store::Db::get_release().unwrap();
+
+    // 按集分组，同一集的多个版本只下载一个
+    let mut groups: HashMap<String, Vec<(String, Subscription)>> = HashMap::new();
+    for (name, item) in feed {
+        match db.get(name.clone()) {
+            Ok(Some(_)) => {
+                // 多个订阅源包含同一剧集时，只由最先看到的订阅源处理
+                match db.get_feed(name.clone()) {
+                    Ok(Some(feed)) if feed != subscribe.name => {
+                        debug!("Already processed {} from feed {}", name, feed);
+                    }
+                    _ => debug!("Already in processed {}", name),
+                }
+                continue;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!("Error when accessing database {}", e);
+                continue;
+            }
+        }
+
+        // 被规则拒绝的剧集不标记为已处理，规则修改后可以重新匹配
+        if let Some(reason) = filter.check(&name, item.anime.bangumi_tv_id) {
+            debug!("Rejected {}: {}", name, reason);
+            rejected.insert(name.clone(), reason).unwrap_or_else(|e| {
+                error!("Error inserting into database: {}", e);
+            });
+            continue;
+        }
+
+        // 无法解析集数的剧集单独处理
+        let key = release::key(&name, item.anime.bangumi_tv_id).unwrap_or_else(|| name.clone());
+        groups.entry(key).or_default().push((name, item));
+    }
+
+    for (key, items) in groups {
+        let names = items.iter().map(|(n, _)| n.clone()).collect::<Vec<_>>();
+        let magnets = items
+            .iter()
+            .map(|(n, item)| (n.clone(), item.magnet.clone()))
+            .collect::<Vec<_>>();
+
+        let ret = releases.merge(key.clone(), magnets, |chosen| {
+            choose(preference, chosen, &names).to_owned()
+        });
+        let (old, chosen) = match ret {
+            Ok(ret) => ret,
+            Err(e) => {
+                error!("Error when accessing database {}", e);
+                continue;
+            }
+        };
+
+        if old.as_ref() == Some(&chosen) {
+            debug!("{} already downloaded as {}", key, chosen);
+        } else {
+            let (_, item) = items.iter().find(|(n, _)| *n == chosen).unwrap();
+            let ret = match old {
+                // 修订版本或更符合偏好的版本，上传后替换已经下载的版本
+                Some(old) => {
+                    info!("{} replaces {}", chosen, old);
+                    download_worker
+                        .add_revision(chosen.clone(), item.clone(), subscribe.name.clone(), old)
+                        .await
+                }
+                None => {
+                    debug!("Processing {}", chosen);
+                    download_worker
+                        .add(chosen.clone(), item.clone(), subscribe.name.clone())
+                        .await
+                }
+            };
+            if let Err(e) = ret {
+                error!("Error adding download task: {}", e);
+            }
+        }
+
+        // Insert into database to avoid duplicate processing
+        for name in names {
+            db.insert(name.clone(), subscribe.name.clone())
+                .unwrap_or_else(|e| {
+                    error!("Error inserting into database: {}", e);
+                });
+            rejected.remove(name).unwrap_or_else(|e| {
+                error!("Error removing from database: {}", e);
+            });
+        }
+    }
+}
+
+// 同一字幕组发布的修订版本（如 [17v2]）优先，其次是比已选版本更符合偏好的版本，
+// 都没有时保留已选版本
+fn choose<'a>(preference: &Preference, chosen: Option<&'a str>, names: &'a [String]) -> &'a str {
+    let best = release::select(preference, names.iter().map(String::as_str));
+    let Some(chosen) = chosen else {
+        return best.unwrap();
+    };
+
+    let revision = names
+        .iter()
+        .filter(|n| is_revision_of(n, chosen))
+        .max_by_key(|n| title::revision(n));
+    if let Some(revision) = revision {
+        return revision;
+    }
+    match best {
+        Some(best) if release::is_better(preference, best, chosen) => best,
+        _ => chosen,
+    }
+}
+
+fn is_revision_of(name: &str, chosen: &str) -> bool {
+    title::subgroup(name) == title::subgroup(chosen)
+        && title::revision(name) > title::revision(chosen)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_choose() {
+        let preference = Preference {
+            subgroups: vec!["LoliHouse".into(), "Sakurato".into()],
+            ..Default::default()
+        };
+        let chosen = "[Sakurato] Dungeon Meshi [17][1080p][CHS].mp4";
+
+        let names = ["[ANi] Dungeon Meshi - 17 [1080P][CHT].mp4".to_owned()];
+        assert_eq!(choose(&preference, None, &names), names[0]);
+        assert_eq!(choose(&preference, Some(chosen), &names), chosen);
+
+        let names = [
+            "[ANi] Dungeon Meshi - 17 [1080P][CHT].mp4".to_owned(),
+            "[LoliHouse] Dungeon Meshi - 17 [1080p][CHS]".to_owned(),
+        ];
+        assert_eq!(choose(&preference, Some(chosen), &names), names[1]);
+
+        let names = [
+            "[LoliHouse] Dungeon Meshi - 17 [1080p][CHS]".to_owned(),
+            "[Sakurato] Dungeon Meshi [17v2][1080p][CHS].mp4".to_owned(),
+        ];
+        assert_eq!(choose(&preference, Some(chosen), &names), names[1]);
+    }
+}
@@ -1,5 +1,26 @@
 mod download;
+mod feed;
 mod upload;
 
 pub use download::DownloadHandle;
+pub use feed::poll_feed;
 pub use upload::upload_video;
+
+// 指数退避，第 attempts 次失败后等待 base * 2^(attempts - 1) 秒，不超过 max
+fn backoff(base: u64, max: u64, attempts: u32) -> u64 {
+    base.saturating_mul(1 << (attempts.max(1) - 1).min(32))
+        .min(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(60, 3600, 0), 60);
+        assert_eq!(backoff(60, 3600, 3), 240);
+        assert_eq!(backoff(60, 3600, 10), 3600);
+        assert_eq!(backoff(u64::MAX / 2, u64::MAX, 100), u64::MAX);
+    }
+}
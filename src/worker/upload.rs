@@ -1,19 +1,32 @@
-use chrono::Datelike;
-use chrono::NaiveDate;
-use std::any::Any;
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use tokio::io::AsyncSeekExt as _;
-use tokio::task::JoinHandle;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Semaphore;
+use tokio::task::{JoinHandle, JoinSet};
 use tracing::info;
 
-use crate::store::Db;
-use crate::util::config::Storage;
+use crate::layout::{Layout, Vars};
+use crate::media;
+use crate::metadata;
+use crate::store::{Db, DownloadTask, HistoryEvent, UploadState};
+use crate::target::{Reader, Remote, Target, Throttle};
+use crate::util::bangumi;
+use crate::util::config::{Existing, Files, Storage, Subscribe, Upload};
 use crate::util::convert_storage;
-use crate::util::llama;
-use crate::util::reqwest::client;
+use crate::util::llama::{self, ContentResponse};
+use crate::util::parser::{self, Confidence};
+use crate::util::title;
 
-pub async fn upload_video(storages: Vec<Storage>) -> JoinHandle<()> {
+pub async fn upload_video(
+    storages: Vec<Storage>,
+    subscribe: Vec<Subscribe>,
+    layout: Layout,
+    files: Files,
+    upload: Upload,
+) -> JoinHandle<()> {
     let backend = convert_storage(storages).await.unwrap();
+    let uploader = Arc::new(Uploader::new(backend, subscribe, layout, files, upload));
     let download_db = Db::get_download().unwrap();
 
     tokio::spawn(async move {
@@ -30,101 +43,25 @@ pub async fn upload_video(storages: Vec<Storage>) -> JoinHandle<()> {
             }
             let ret = ret.unwrap();
 
-            for (name, task) in ret {
-                match task.state {
-                    crate::store::DownloadTaskState::Downloaded {
-                        file_path,
-                        info_hash,
-                    } => {
-                        let video_path = find_video_in_path(&file_path).await;
-                        if video_path.is_none() {
-                            tracing::error!("No video found in {:?}", file_path);
-                            // set state to Finished
-                            download_db
-                                .update_state(
-                                    name.clone(),
-                                    crate::store::DownloadTaskState::Finished {
-                                        file_path: file_path.clone(),
-                                        info_hash,
-                                        finish_time: chrono::Utc::now().timestamp() as u64,
-                                    },
-                                )
-                                .unwrap_or_else(|e| {
-                                    tracing::error!("Error updating state: {}", e);
-                                });
-                            continue;
-                        }
-                        let video_path = video_path.unwrap();
-
-                        let bangumi_id = task.bangumi_id;
-                        let video_name = generate_file_name(&video_path, bangumi_id).await;
-                        let upload_path = Path::new(generate_folder_name(task.air_date).as_str())
-                            .join(task.weekday)
-                            .join(task.anime_title)
-                            .join(video_name);
-
-                        let file = tokio::fs::File::open(&video_path).await;
-                        if let Err(e) = file {
-                            tracing::error!("Error opening file {}: {}", video_path.display(), e);
-                            continue;
-                        }
-                        let file = file.unwrap();
-                        let size = file.metadata().await.unwrap().len();
-
-                        // 标记是否上传成功
-                        let mut success = true;
-                        for (name, backend) in &backend {
-                            let file = file.try_clone().await;
-                            if let Err(e) = file {
-                                tracing::error!("Error cloning file: {}", e);
-                                success = false;
-                                continue;
-                            }
-                            let mut file = file.unwrap();
-                            file.seek(std::io::SeekFrom::Start(0)).await.unwrap();
-                            let reader = tokio::io::BufReader::new(file);
-
-                            let ret = backend
-                                .upload(Box::new(reader), size, upload_path.clone())
-                                .await;
-                            if let Err(e) = ret {
-                                tracing::error!("Error uploading: {}", e);
-                                success = false;
-                                continue;
-                            }
-
-                            // if downcast onedrive backend successful
-                            let b = backend as &dyn Any;
-                            if let Some(b) = b.downcast_ref::<upload_backend::backend::Onedrive>() {
-                                let refresh_token = b.refresh_token();
-                                let db = Db::get_onedrive().unwrap();
-                                db.insert_refresh_token(refresh_token, name.clone())
-                                    .unwrap_or_else(|e| {
-                                        tracing::error!("Error inserting refresh token: {}", e);
-                                    });
-                            }
-                        }
+            for name in ret.into_keys() {
+                // 上一轮还没有上传完的任务
+                if !RUNNING.lock().unwrap().insert(name.clone()) {
+                    continue;
+                }
 
-                        // set state to Finished
-                        if success {
-                            download_db
-                                .update_state(
-                                    name.clone(),
-                                    crate::store::DownloadTaskState::Finished {
-                                        file_path: file_path.clone(),
-                                        info_hash,
-                                        finish_time: chrono::Utc::now().timestamp() as u64,
-                                    },
-                                )
-                                .unwrap_or_else(|e| {
-                                    tracing::error!("Error updating state: {}", e);
-                                });
-
-                            info!("Uploaded: {}", name);
+                let uploader = uploader.clone();
+                let download_db = download_db.clone();
+                tokio::spawn(async move {
+                    // 加入 RUNNING 之后重新读取，期间被修订版本取消的任务不再上传
+                    match download_db.get(name.clone()) {
+                        Ok(Some(task)) if is_downloaded(&task) => {
+                            uploader.upload_task(&name, task).await;
                         }
+                        Ok(_) => {}
+                        Err(e) => tracing::error!("Error getting download task: {}", e),
                     }
-                    _ => unreachable!(),
-                }
+                    RUNNING.lock().unwrap().remove(&name);
+                });
             }
 
             tokio::time::sleep(std::time::Duration::from_secs(60)).await;
@@ -132,157 +69,888 @@ pub async fn upload_video(storages: Vec<Storage>) -> JoinHandle<()> {
     })
 }
 
-fn generate_folder_name(date: NaiveDate) -> String {
-    let year = date.year();
-    let quarters = [
-        NaiveDate::from_ymd_opt(year, 1, 1).unwrap(),
-        NaiveDate::from_ymd_opt(year, 4, 1).unwrap(),
-        NaiveDate::from_ymd_opt(year, 7, 1).unwrap(),
-        NaiveDate::from_ymd_opt(year, 10, 1).unwrap(),
-    ];
-
-    // Find the nearest quarter start date
-    let nearest_quarter = quarters
-        .iter()
-        .min_by_key(|&&q| (date - q).num_days().abs())
-        .unwrap();
-
-    // Get the month of the nearest quarter
-    let month = nearest_quarter.month();
-
-    format!("{}年{}月", year, month)
+// 正在上传的任务，被取消的任务在上传结束前不能删除本地文件
+static RUNNING: Lazy<Mutex<HashSet<String>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+pub fn is_uploading(name: &str) -> bool {
+    RUNNING.lock().unwrap().contains(name)
+}
+
+fn is_downloaded(task: &DownloadTask) -> bool {
+    matches!(
+        task.state,
+        crate::store::DownloadTaskState::Downloaded { .. }
+    )
 }
 
-async fn find_video_in_path(path: &Path) -> Option<PathBuf> {
-    if !path.exists() {
-        return None;
+struct Uploader {
+    backend: HashMap<String, Box<dyn Target>>,
+    // 每个订阅源对应的上传目标，为空时上传到所有存储
+    targets: HashMap<String, Vec<String>>,
+    layout: Layout,
+    files: Files,
+    retry: Retry,
+    // 同时上传的总数
+    global: Semaphore,
+    throttle: Option<Arc<Throttle>>,
+    limits: HashMap<String, Limit>,
+    existing: Existing,
+    size_only: bool,
+}
+
+// 单个上传目标的并发数和限速
+struct Limit {
+    semaphore: Semaphore,
+    throttle: Option<Arc<Throttle>>,
+}
+
+impl Uploader {
+    fn new(
+        backend: HashMap<String, Box<dyn Target>>,
+        subscribe: Vec<Subscribe>,
+        layout: Layout,
+        files: Files,
+        upload: Upload,
+    ) -> Self {
+        let limits = backend
+            .keys()
+            .map(|name| {
+                let config = upload.targets.get(name);
+                let concurrency = config
+                    .and_then(|c| c.concurrency)
+                    .unwrap_or(upload.target_concurrency);
+                let limit = Limit {
+                    semaphore: Semaphore::new(concurrency.max(1)),
+                    throttle: config.and_then(|c| c.bytes_per_sec).map(Throttle::new),
+                };
+                (name.clone(), limit)
+            })
+            .collect();
+
+        Self {
+            backend,
+            targets: subscribe.into_iter().map(|s| (s.name, s.storage)).collect(),
+            layout,
+            files,
+            retry: Retry {
+                max_attempts: upload.max_attempts,
+                base: upload.retry_base_secs,
+                max: upload.retry_max_secs,
+            },
+            global: Semaphore::new(upload.concurrency.max(1)),
+            throttle: upload.bytes_per_sec.map(Throttle::new),
+            limits,
+            existing: upload.existing,
+            size_only: upload.size_only,
+        }
     }
 
-    if !path.is_dir() {
-        if let Some(ext) = path.extension() {
-            if ext == "mp4" || ext == "mkv" {
-                return Some(path.to_owned());
+    async fn upload_task(self: &Arc<Self>, name: &str, task: DownloadTask) {
+        let download_db = Db::get_download().unwrap();
+        let crate::store::DownloadTaskState::Downloaded {
+            file_path,
+            info_hash,
+        } = task.state.clone()
+        else {
+            unreachable!()
+        };
+
+        let files = media::group(media::scan(&file_path).await, &self.files);
+        if files.media.is_empty() {
+            tracing::error!("No video found in {:?}", file_path);
+            // set state to Finished
+            download_db
+                .update_state(
+                    name.to_owned(),
+                    crate::store::DownloadTaskState::Finished {
+                        file_path: file_path.clone(),
+                        info_hash,
+                        finish_time: chrono::Utc::now().timestamp() as u64,
+                    },
+                )
+                .unwrap_or_else(|e| {
+                    tracing::error!("Error updating state: {}", e);
+                });
+            return;
+        }
+
+        let feed_targets = self.targets.get(&task.feed).filter(|t| !t.is_empty());
+        let task_targets = self
+            .backend
+            .keys()
+            .filter(|t| feed_targets.is_none_or(|f| f.contains(t)))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        // 已上传、已放弃或还没到重试时间的目标不需要上传
+        let now = chrono::Utc::now().timestamp() as u64;
+        let due = task_targets
+            .iter()
+            .filter(|t| match task.uploads.get(*t) {
+                None => true,
+                Some(UploadState::Failed { retry_at, .. }) => *retry_at <= now,
+                Some(
+                    UploadState::Uploaded | UploadState::Unverified | UploadState::GaveUp { .. },
+                ) => false,
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        // 需要重新授权的目标等待用户登录，任务保持在 Downloaded
+        let mut authorized = Vec::with_capacity(due.len());
+        for target_name in due {
+            if self.backend[&target_name].needs_reauth().await {
+                tracing::warn!("Skip uploading {} to {}: needs reauth", name, target_name);
+            } else {
+                authorized.push(target_name);
             }
         }
-        return None;
+        let due = authorized;
+
+        // 没有需要上传的目标时也要汇总状态，所有目标都已结束或修改配置后没有目标的任务
+        // 不能一直停留在 Downloaded
+        let mut uploads = task.uploads.clone();
+        if !due.is_empty() {
+            let items = generate_items(&self.layout, name, &task, files).await;
+            uploads.extend(self.upload_targets(name, &task, items, due).await);
+        }
+        let missing = task_targets
+            .iter()
+            .filter(|t| {
+                !matches!(
+                    uploads.get(*t),
+                    Some(UploadState::Uploaded | UploadState::Unverified)
+                )
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        // 还有等待重试的目标
+        if missing
+            .iter()
+            .any(|t| !matches!(uploads.get(t), Some(UploadState::GaveUp { .. })))
+        {
+            return;
+        }
+
+        // 上传期间被修订版本取消，保留取消状态，由下载线程删除本地文件
+        match download_db.get(name.to_owned()) {
+            Ok(Some(task)) if is_downloaded(&task) => {}
+            Ok(_) => {
+                info!("Upload of {} finished after it was superseded", name);
+                return;
+            }
+            Err(e) => {
+                tracing::error!("Error getting download task: {}", e);
+                return;
+            }
+        }
+
+        if let Some(old) = &task.supersedes {
+            supersede(&self.backend, old, name).await;
+        }
+
+        let finish_time = chrono::Utc::now().timestamp() as u64;
+        // 没有任何上传目标时记为部分上传，本地文件是唯一的副本，不按做种策略删除
+        let state = if missing.is_empty() && !task_targets.is_empty() {
+            info!("Uploaded: {}", name);
+            crate::store::DownloadTaskState::Finished {
+                file_path,
+                info_hash,
+                finish_time,
+            }
+        } else {
+            if task_targets.is_empty() {
+                tracing::warn!("No storage to upload {} from feed {}", name, task.feed);
+            } else {
+                tracing::warn!("Partially uploaded: {}, missing {:?}", name, missing);
+            }
+            crate::store::DownloadTaskState::Partial {
+                file_path,
+                info_hash,
+                finish_time,
+                missing,
+            }
+        };
+        download_db
+            .update_state(name.to_owned(), state)
+            .unwrap_or_else(|e| {
+                tracing::error!("Error updating state: {}", e);
+            });
+    }
+
+    // 并行上传到各个目标，互不影响，返回有变化的上传状态
+    async fn upload_targets(
+        self: &Arc<Self>,
+        name: &str,
+        task: &DownloadTask,
+        items: Vec<Item>,
+        due: Vec<String>,
+    ) -> Vec<(String, UploadState)> {
+        let download_db = Db::get_download().unwrap();
+        let items = Arc::new(items);
+        // 修订版本上传到旧版本的路径，直接覆盖，不能改名后保留两份，
+        // 也不能因为只比较了大小而跳过
+        let existing = match task.supersedes {
+            Some(_) => Existing::Overwrite,
+            None => self.existing,
+        };
+
+        let mut set = JoinSet::new();
+        for target_name in due {
+            let this = self.clone();
+            let name = name.to_owned();
+            let items = items.clone();
+            let previous = task.uploads.get(&target_name).cloned();
+            set.spawn(async move {
+                let state = this
+                    .upload_target(&name, &target_name, &items, existing, previous)
+                    .await;
+                (target_name, state)
+            });
+        }
+
+        let mut uploads = Vec::new();
+        while let Some(ret) = set.join_next().await {
+            let (target_name, state) = match ret {
+                Ok(ret) => ret,
+                Err(e) => {
+                    tracing::error!("Upload task panicked: {}", e);
+                    continue;
+                }
+            };
+            let Some(state) = state else {
+                continue;
+            };
+            download_db
+                .update_upload(name.to_owned(), target_name.clone(), state.clone())
+                .unwrap_or_else(|e| {
+                    tracing::error!("Error updating upload state: {}", e);
+                });
+            uploads.push((target_name, state));
+        }
+        uploads
     }
 
-    let mut entries = tokio::fs::read_dir(path).await.ok()?;
-    while let Some(entry) = entries.next_entry().await.ok()? {
-        let path = entry.path();
-        if path.is_dir() {
-            let ret = Box::pin(find_video_in_path(&path)).await;
-            if ret.is_some() {
-                return ret;
+    // 在并发限制内上传到一个目标，返回该目标新的上传状态，授权失效时返回 None 保持原状态
+    async fn upload_target(
+        &self,
+        name: &str,
+        target_name: &str,
+        items: &[Item],
+        existing: Existing,
+        previous: Option<UploadState>,
+    ) -> Option<UploadState> {
+        let limit = &self.limits[target_name];
+        let _target_permit = limit.semaphore.acquire().await.unwrap();
+        let _permit = self.global.acquire().await.unwrap();
+
+        let throttles = [&self.throttle, &limit.throttle]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        let backend = self.backend[target_name].as_ref();
+        match upload_items(name, target_name, backend, items, &throttles, existing).await {
+            Ok(Verified::Checksum) => Some(UploadState::Uploaded),
+            Ok(Verified::Size) if self.size_only => Some(UploadState::Uploaded),
+            Ok(Verified::Size) => {
+                tracing::warn!(
+                    "Uploaded {} to {} without checksum, only the size was compared",
+                    name,
+                    target_name
+                );
+                Some(UploadState::Unverified)
+            }
+            Err(error) if backend.needs_reauth().await => {
+                tracing::warn!("Upload {} to {} paused: {}", name, target_name, error);
+                None
             }
-        } else if let Some(ext) = path.extension() {
-            if ext == "mp4" || ext == "mkv" {
-                return Some(path);
+            Err(error) => {
+                let attempts = match previous {
+                    Some(UploadState::Failed { attempts, .. }) => attempts + 1,
+                    _ => 1,
+                };
+                Some(self.retry.next_state(name, target_name, attempts, error))
             }
         }
     }
-    None
 }
 
-async fn generate_file_name(path: &Path, bangumi_id: u64) -> String {
-    if let Some(l) = llama::Llama::get() {
-        let file_name = path.file_name().unwrap().to_str().unwrap().to_string();
+/// 上传失败后的重试策略
+struct Retry {
+    max_attempts: u32,
+    base: u64,
+    max: u64,
+}
 
-        let ext = path.extension().unwrap().to_str().unwrap().to_string();
-        info!("Use llama to decode {}", file_name);
-        let ret = l.decode(&file_name).await;
-        match ret {
-            Ok(ret) => {
-                info!("Decode result: {:?}", ret);
-                let episode = ret.episode;
-
-                let client = client()
-                    .get(format!(
-                        "https://api.bgm.tv/v0/episodes?subject_id={}&type=0&limit=1000",
-                        bangumi_id
-                    ))
-                    .header("User-Agent", "Chikage0o0/mikan-subscriber")
-                    .send()
-                    .await;
-                match client {
-                    Ok(client) => {
-                        let client = client.json::<BangumiEpisode>().await;
-                        if let Ok(client) = client {
-                            for i in client.data {
-                                if i.ep == episode || i.sort == episode {
-                                    if i.name_cn.is_empty() {
-                                        return format!(
-                                            "{:02} - {}.{}",
-                                            episode,
-                                            sanitize_filename(&i.name),
-                                            ext
-                                        );
-                                    } else {
-                                        return format!(
-                                            "{:02} - {}.{}",
-                                            episode,
-                                            sanitize_filename(&i.name_cn),
-                                            ext
-                                        );
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        tracing::error!("Error when get_name_from_bangumi: {}", e);
-                    }
+impl Retry {
+    // 指数退避，超过最大次数后放弃并记录到历史中
+    fn next_state(&self, name: &str, target: &str, attempts: u32, error: String) -> UploadState {
+        if attempts >= self.max_attempts {
+            tracing::error!("Giving up uploading {} to {}: {}", name, target, error);
+            Db::get_history()
+                .unwrap()
+                .push(
+                    name.to_owned(),
+                    HistoryEvent::UploadFailed {
+                        target: target.to_owned(),
+                        error: error.clone(),
+                    },
+                )
+                .unwrap_or_else(|e| {
+                    tracing::error!("Error updating history: {}", e);
+                });
+            return UploadState::GaveUp { attempts, error };
+        }
+
+        UploadState::Failed {
+            attempts,
+            error,
+            retry_at: chrono::Utc::now().timestamp() as u64
+                + super::backoff(self.base, self.max, attempts),
+        }
+    }
+}
+
+// 删除旧版本中与新版本路径不同的远程文件，路径相同的已经被覆盖
+async fn supersede(backend: &HashMap<String, Box<dyn Target>>, old: &str, new: &str) {
+    let history_db = Db::get_history().unwrap();
+    let old_history = history_db.get(old.to_owned());
+    let new_history = history_db.get(new.to_owned());
+    let (old_history, new_history) = match (old_history, new_history) {
+        (Ok(old), Ok(new)) => (old, new),
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::error!("Error getting history: {}", e);
+            return;
+        }
+    };
+    let new_uploaded = new_history.uploaded();
+
+    for (target_name, path) in old_history.uploaded() {
+        if new_uploaded.contains(&(target_name.clone(), path.clone())) {
+            continue;
+        }
+        // 新版本没有上传到该目标时保留旧版本
+        if !new_uploaded.iter().any(|(t, _)| *t == target_name) {
+            continue;
+        }
+        let Some(target) = backend.get(&target_name) else {
+            continue;
+        };
+
+        info!(
+            "Deleting superseded {} from {}",
+            path.display(),
+            target_name
+        );
+        target.delete(&path).await.unwrap_or_else(|e| {
+            tracing::error!("Error deleting {}: {}", path.display(), e);
+        });
+    }
+
+    history_db
+        .push(
+            old.to_owned(),
+            HistoryEvent::Superseded { by: new.to_owned() },
+        )
+        .unwrap_or_else(|e| {
+            tracing::error!("Error updating history: {}", e);
+        });
+}
+
+// 一个需要上传的文件
+struct Item {
+    source: Source,
+    path: PathBuf,
+    /// 上传失败时任务视为失败，nfo 和海报等失败不影响任务
+    required: bool,
+    /// 多个剧集共用的文件，不记录到历史中，避免旧版本被替代时删除
+    shared: bool,
+    /// 跟随的视频的上传路径，视频改名时字幕和 nfo 一起改名
+    video: Option<PathBuf>,
+}
+
+enum Source {
+    File(PathBuf),
+    Memory(Vec<u8>),
+}
+
+impl Item {
+    async fn size(&self) -> std::io::Result<u64> {
+        match &self.source {
+            Source::File(path) => Ok(tokio::fs::metadata(path).await?.len()),
+            Source::Memory(data) => Ok(data.len() as u64),
+        }
+    }
+
+    async fn reader(&self) -> std::io::Result<(Reader, u64)> {
+        match &self.source {
+            Source::File(path) => {
+                let file = tokio::fs::File::open(path).await?;
+                let size = file.metadata().await?.len();
+                Ok((Box::new(tokio::io::BufReader::new(file)), size))
+            }
+            Source::Memory(data) => Ok((
+                Box::new(std::io::Cursor::new(data.clone())),
+                data.len() as u64,
+            )),
+        }
+    }
+}
+
+// 上传任务的所有文件到一个目标，必需的文件上传失败时返回第一个错误，
+// 成功时返回必需的文件中最弱的校验方式
+async fn upload_items(
+    name: &str,
+    target_name: &str,
+    backend: &dyn Target,
+    items: &[Item],
+    throttles: &[&Arc<Throttle>],
+    existing: Existing,
+) -> Result<Verified, String> {
+    let history_db = Db::get_history().unwrap();
+
+    let renames = match existing {
+        Existing::Rename => rename_videos(backend, items).await?,
+        Existing::Skip | Existing::Overwrite => HashMap::new(),
+    };
+
+    let mut ret = Ok(Verified::Checksum);
+    for item in items {
+        let path = renamed_path(item, &renames);
+        let uploaded = upload_item(backend, item, &path, throttles, existing).await;
+        if let (Ok(verified), Ok((_, v))) = (&mut ret, &uploaded) {
+            if item.required {
+                *verified = (*verified).min(*v);
+            }
+        }
+        let event = match uploaded {
+            Ok((true, _)) => HistoryEvent::Uploaded {
+                target: target_name.to_owned(),
+                path,
+            },
+            Ok((false, _)) => {
+                info!("Skipped existing {} on {}", path.display(), target_name);
+                HistoryEvent::Skipped {
+                    target: target_name.to_owned(),
+                    path,
                 }
             }
             Err(e) => {
-                tracing::error!("Error decoding {}: {}", file_name, e);
+                tracing::error!("Error uploading {}: {}", path.display(), e);
+                if item.required && ret.is_ok() {
+                    ret = Err(e);
+                }
+                continue;
             }
+        };
+
+        if item.shared {
+            continue;
         }
+        history_db.push(name.to_owned(), event).unwrap_or_else(|e| {
+            tracing::error!("Error updating history: {}", e);
+        });
     }
 
-    path.file_name().unwrap().to_str().unwrap().to_string()
+    ret
 }
 
-fn sanitize_filename(filename: &str) -> String {
-    // Define a set of special characters to be replaced
-    let special_chars = ['\\', '/', ':', '*', '?', '"', '<', '>', '|'];
+// 上传一个文件，返回是否上传和校验方式，远程已经存在相同的文件时跳过
+async fn upload_item(
+    backend: &dyn Target,
+    item: &Item,
+    path: &Path,
+    throttles: &[&Arc<Throttle>],
+    existing: Existing,
+) -> Result<(bool, Verified), String> {
+    if existing != Existing::Overwrite {
+        // 大小相同但校验值不同的文件可能不完整，重新上传覆盖
+        if let Some(remote) = backend.stat(path).await.map_err(|e| e.to_string())? {
+            if let Some(verified) = compare(item, &remote).await? {
+                return Ok((false, verified));
+            }
+        }
+    }
+
+    let linked = match &item.source {
+        Source::File(source) => backend
+            .link(source, path)
+            .await
+            .map_err(|e| e.to_string())?,
+        Source::Memory(_) => false,
+    };
+    if !linked {
+        let (reader, size) = item.reader().await.map_err(|e| e.to_string())?;
+        let reader = throttles.iter().fold(reader, |r, t| t.wrap(r));
+        backend
+            .upload(reader, size, path)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
 
-    // Replace each special character with an underscore
-    filename
-        .chars()
-        .map(|c| if special_chars.contains(&c) { '_' } else { c })
-        .collect()
+    let verified = verify(backend, item, path).await?;
+    Ok((true, verified))
 }
 
-#[derive(Debug, serde::Deserialize)]
-struct BangumiEpisode {
-    pub data: Vec<Episode>,
+// 远程已经存在内容不同的同名视频时，找到第一个可用的带后缀的文件名
+async fn rename_videos(
+    backend: &dyn Target,
+    items: &[Item],
+) -> Result<HashMap<PathBuf, PathBuf>, String> {
+    let mut renames = HashMap::new();
+    for item in items {
+        if item.video.as_ref() != Some(&item.path) {
+            continue;
+        }
+
+        let mut n = 0;
+        let path = loop {
+            let path = with_suffix(&item.path, n);
+            let usable = match backend.stat(&path).await.map_err(|e| e.to_string())? {
+                Some(remote) => compare(item, &remote).await?.is_some(),
+                None => true,
+            };
+            if usable {
+                break path;
+            }
+            n += 1;
+        };
+        if n > 0 {
+            info!("Renaming {} to {}", item.path.display(), path.display());
+            renames.insert(item.path.clone(), path);
+        }
+    }
+    Ok(renames)
 }
-#[derive(Debug, serde::Deserialize)]
-struct Episode {
-    name: String,
-    name_cn: String,
-    sort: u32,
-    ep: u32,
+
+// 在文件名后加上 (n)，n 为 0 时不变
+fn with_suffix(path: &Path, n: u32) -> PathBuf {
+    if n == 0 {
+        return path.to_owned();
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    match path.extension() {
+        Some(ext) => path.with_file_name(format!("{} ({}).{}", stem, n, ext.to_string_lossy())),
+        None => path.with_file_name(format!("{} ({})", stem, n)),
+    }
+}
+
+// 视频改名后，字幕和 nfo 的文件名中与视频相同的部分一起替换
+fn renamed_path(item: &Item, renames: &HashMap<PathBuf, PathBuf>) -> PathBuf {
+    let Some((video, renamed)) = item
+        .video
+        .as_ref()
+        .and_then(|v| renames.get(v).map(|r| (v, r)))
+    else {
+        return item.path.clone();
+    };
+
+    let stem = video.file_stem().unwrap_or_default().to_string_lossy();
+    let new_stem = renamed.file_stem().unwrap_or_default().to_string_lossy();
+    let name = item.path.file_name().unwrap_or_default().to_string_lossy();
+    match name.strip_prefix(stem.as_ref()) {
+        Some(rest) => item.path.with_file_name(format!("{}{}", new_stem, rest)),
+        None => item.path.clone(),
+    }
+}
+
+/// 远程文件的校验方式，按可靠程度排序
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Verified {
+    /// 目标不提供校验值，只比较了大小
+    Size,
+    Checksum,
+}
+
+// 上传后比较远程文件的大小和校验值，通过后才视为上传成功
+async fn verify(backend: &dyn Target, item: &Item, path: &Path) -> Result<Verified, String> {
+    let remote = backend
+        .stat(path)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "file not found after upload".to_owned())?;
+    compare(item, &remote)
+        .await?
+        .ok_or_else(|| format!("size or checksum mismatch after upload: {:?}", remote))
+}
+
+// 比较远程文件与本地文件，大小或校验值不同时返回 None
+async fn compare(item: &Item, remote: &Remote) -> Result<Option<Verified>, String> {
+    let size = item.size().await.map_err(|e| e.to_string())?;
+    if remote.size != size {
+        return Ok(None);
+    }
+    let Some(checksum) = &remote.checksum else {
+        return Ok(Some(Verified::Size));
+    };
+    let (reader, _) = item.reader().await.map_err(|e| e.to_string())?;
+    let same = checksum.verify(reader).await.map_err(|e| e.to_string())?;
+    Ok(same.then_some(Verified::Checksum))
+}
+
+// 为每个视频生成上传路径，字幕和元数据跟随视频
+async fn generate_items(
+    layout: &Layout,
+    name: &str,
+    task: &DownloadTask,
+    files: media::Files,
+) -> Vec<Item> {
+    let subject = if layout.metadata {
+        bangumi::subject(task.bangumi_id)
+            .await
+            .map_err(|e| tracing::error!("Error getting subject {}: {}", task.bangumi_id, e))
+            .ok()
+    } else {
+        None
+    };
+    let show_files = match &subject {
+        Some(subject) => show_metadata(subject).await,
+        None => Vec::new(),
+    };
+
+    let mut items: Vec<Item> = Vec::new();
+    for media in files.media {
+        let (upload_path, decoded) = generate_path(layout, name, task, &media.video).await;
+
+        for subtitle in media.subtitles {
+            items.push(Item {
+                path: media::subtitle_path(&media.video, &upload_path, &subtitle),
+                source: Source::File(subtitle),
+                required: true,
+                shared: false,
+                video: Some(upload_path.clone()),
+            });
+        }
+
+        for font in &files.fonts {
+            let path = media::font_path(&upload_path, font);
+            if items.iter().all(|i| i.path != path) {
+                items.push(Item {
+                    source: Source::File(font.clone()),
+                    path,
+                    required: true,
+                    shared: true,
+                    video: None,
+                });
+            }
+        }
+
+        if let Some(subject) = &subject {
+            let show_dir = layout.show_dir(&upload_path);
+            for (file_name, data) in &show_files {
+                let path = show_dir.join(file_name);
+                if items.iter().all(|i| i.path != path) {
+                    items.push(Item {
+                        source: Source::Memory(data.clone()),
+                        path,
+                        required: false,
+                        shared: true,
+                        video: None,
+                    });
+                }
+            }
+
+            if let Some((ret, episode)) = &decoded {
+                let nfo = metadata::episode(subject, ret.season, ret.episode, episode.as_ref());
+                items.push(Item {
+                    source: Source::Memory(nfo.into_bytes()),
+                    path: upload_path.with_extension("nfo"),
+                    required: false,
+                    shared: false,
+                    video: Some(upload_path.clone()),
+                });
+            }
+        }
+
+        items.push(Item {
+            source: Source::File(media.video),
+            path: upload_path.clone(),
+            required: true,
+            shared: false,
+            video: Some(upload_path),
+        });
+    }
+
+    items
+}
+
+// 整部剧共用的 tvshow.nfo 和海报
+async fn show_metadata(subject: &bangumi::Subject) -> Vec<(&'static str, Vec<u8>)> {
+    let mut files = vec![("tvshow.nfo", metadata::tvshow(subject).into_bytes())];
+
+    match bangumi::poster(subject).await {
+        Ok(Some(poster)) => files.push(("poster.jpg", poster)),
+        Ok(None) => {}
+        Err(e) => tracing::error!("Error downloading poster: {}", e),
+    }
+
+    files
+}
+
+async fn generate_path(
+    layout: &Layout,
+    name: &str,
+    task: &DownloadTask,
+    path: &Path,
+) -> (PathBuf, Option<Decoded>) {
+    let file_name = path.file_name().unwrap().to_str().unwrap().to_string();
+    let decoded = decode_episode(path, task.bangumi_id).await;
+
+    let vars = Vars {
+        title: task.anime_title.clone(),
+        season: decoded.as_ref().map(|(ret, _)| ret.season),
+        episode: decoded.as_ref().map(|(ret, _)| ret.episode),
+        episode_name: decoded
+            .as_ref()
+            .and_then(|(_, episode)| episode.as_ref())
+            .map(|i| {
+                if i.name_cn.is_empty() {
+                    i.name.clone()
+                } else {
+                    i.name_cn.clone()
+                }
+            }),
+        air_date: Some(task.air_date),
+        weekday: task.weekday.clone(),
+        subgroup: title::subgroup(name)
+            .or_else(|| title::subgroup(&file_name))
+            .map(ToOwned::to_owned),
+        resolution: title::resolution(name).or_else(|| title::resolution(&file_name)),
+        ext: path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_string(),
+    };
+
+    (layout.path(&vars, &file_name), decoded)
+}
+
+// 文件名的解析结果和 bangumi 上对应的剧集
+type Decoded = (ContentResponse, Option<bangumi::Episode>);
+
+// 解析文件名中的季数和集数，并从 bangumi 获取该集的信息
+async fn decode_episode(path: &Path, bangumi_id: u64) -> Option<Decoded> {
+    let file_name = path.file_name()?.to_str()?;
+
+    let ret = decode_file_name(file_name).await?;
+    info!("Decode result: {:?}", ret);
+
+    let episode = match bangumi::episodes(bangumi_id).await {
+        Ok(episodes) => episodes.find(ret.episode).cloned(),
+        Err(e) => {
+            tracing::error!("Error when get_name_from_bangumi: {}", e);
+            None
+        }
+    };
+
+    Some((ret, episode))
+}
+
+// 优先使用内置的解析器，只有在结果不可靠时才使用 llama
+async fn decode_file_name(file_name: &str) -> Option<ContentResponse> {
+    let parsed = parser::parse(file_name);
+    if let Some((content, Confidence::High)) = parsed {
+        return Some(content);
+    }
+
+    let cache = Db::get_cache().unwrap();
+    match cache.get_decode(file_name) {
+        Ok(Some(content)) => return Some(content),
+        Ok(None) => {}
+        Err(e) => tracing::error!("Error reading decode cache: {}", e),
+    }
+
+    if let Some(l) = llama::Llama::get() {
+        info!("Use llama to decode {}", file_name);
+        match l.decode(file_name).await {
+            Ok(ret) => {
+                cache
+                    .insert_decode(file_name, ret.clone())
+                    .unwrap_or_else(|e| {
+                        tracing::error!("Error writing decode cache: {}", e);
+                    });
+                return Some(ret);
+            }
+            Err(e) => {
+                tracing::error!("Error decoding {}: {}", file_name, e);
+            }
+        }
+    }
+
+    parsed.map(|(content, _)| content)
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+
+    use super::{renamed_path, with_suffix, Item, Source};
+    use crate::store::UploadState;
     use crate::util::{self, llama, reqwest::init_client};
 
     #[tokio::test]
-    async fn test_generate_file_name() {
+    async fn test_decode_episode() {
         let settings = util::config::Settings::load_from_file("settings.json").unwrap();
         let _ = init_client(settings.proxy).unwrap();
         if let Some(llama) = settings.llama {
-            llama::Llama::init(&llama.model, &llama.url, &llama.token).unwrap();
+            llama::Llama::init(&llama).unwrap();
         }
         let path = std::path::Path::new(
             "[Up to 21°C] Henjin no Salad Bowl - 09 (CR 1920x1080 AVC AAC MKV) [37D7B6CE].mkv",
         );
         let bangumi_id = 444403;
-        let file_name = super::generate_file_name(path, bangumi_id).await;
+        let ret = super::decode_episode(path, bangumi_id).await;
+
+        println!("{:?}", ret);
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let retry = super::Retry {
+            max_attempts: 10,
+            base: 60,
+            max: 600,
+        };
+        let now = chrono::Utc::now().timestamp() as u64;
+        for (attempts, delay) in [(1, 60), (2, 120), (4, 480), (5, 600), (9, 600)] {
+            match retry.next_state("name", "target", attempts, "error".into()) {
+                UploadState::Failed { retry_at, .. } => {
+                    assert!((now + delay..=now + delay + 1).contains(&retry_at));
+                }
+                state => panic!("unexpected state {:?}", state),
+            }
+        }
+    }
+
+    #[test]
+    fn test_rename() {
+        let video = PathBuf::from("Title/Season 01/Title - S01E02 - Name.mkv");
+        assert_eq!(with_suffix(&video, 0), video);
+        let renamed = with_suffix(&video, 2);
+        assert_eq!(
+            renamed,
+            Path::new("Title/Season 01/Title - S01E02 - Name (2).mkv")
+        );
+
+        let renames = HashMap::from([(video.clone(), renamed)]);
+        let subtitle = Item {
+            source: Source::Memory(Vec::new()),
+            path: "Title/Season 01/Title - S01E02 - Name.chs.ass".into(),
+            required: true,
+            shared: false,
+            video: Some(video),
+        };
+        assert_eq!(
+            renamed_path(&subtitle, &renames),
+            Path::new("Title/Season 01/Title - S01E02 - Name (2).chs.ass")
+        );
 
-        println!("{}", file_name);
+        let poster = Item {
+            video: None,
+            path: "Title/poster.jpg".into(),
+            ..subtitle
+        };
+        assert_eq!(renamed_path(&poster, &renames), poster.path);
     }
 }